@@ -1,35 +1,70 @@
+use crate::image::Image;
+use crate::logging::{self, HTTP_TARGET};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
 use std_prelude::Duration;
+use tracing::{debug, info, trace, Instrument};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ApiResponse {
-    pub id: String,
+pub const DEFAULT_BASE_URL: &str = "https://openrouter.ai/api/v1";
+const DEFAULT_CHAT_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+pub const DEFAULT_API_KEY_ENV: &str = "OPENROUTER_API_KEY";
+
+/// A single chat completion request, independent of the provider serving it.
+/// `system` holds the system prompts sent ahead of `contents`, and `history`
+/// the earlier turns of a conversation, sent in between.
+/// `response_format` is passed through to the API as-is, and `images` are
+/// attached to the last user message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatRequest {
     pub model: String,
-    pub object: String,
-    pub created: u64,
-    pub choices: Vec<Choice>,
-    #[serde(default)]
-    pub system_fingerprint: Option<String>,
-    pub usage: Usage,
+    pub system: Vec<String>,
+    pub history: Vec<Turn>,
+    pub contents: Vec<String>,
+    pub temperature: Option<f64>,
+    pub response_format: Option<serde_json::Value>,
+    pub images: Vec<Image>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Choice {
-    pub index: u64,
-    pub message: Message,
-    #[serde(default)]
-    pub finish_reason: Option<String>,
+/// A message of an earlier turn of a conversation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Message {
-    pub role: String,
-    pub content: String,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    User,
+    Assistant,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// One part of a multimodal message, in the OpenAI content-part format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: u64,
     pub completion_tokens: u64,
@@ -53,6 +88,13 @@ pub enum ApiError {
     ResponseParseFailed(serde_json::Error),
     ApiErrorResponse(String),
     RetryExhausted,
+    Cancelled,
+    MissingApiKey(String),
+    Unauthorized(String),
+    RateLimited(String),
+    ModelUnavailable(String),
+    /// The answer hit the output token limit (`finish_reason` `length`)
+    Truncated,
 }
 
 impl std::fmt::Display for ApiError {
@@ -62,129 +104,406 @@ impl std::fmt::Display for ApiError {
             ApiError::ResponseParseFailed(e) => write!(f, "Failed to parse response: {}", e),
             ApiError::ApiErrorResponse(e) => write!(f, "API error: {}", e),
             ApiError::RetryExhausted => write!(f, "Retry attempts exhausted"),
+            ApiError::Cancelled => write!(f, "Request cancelled"),
+            ApiError::MissingApiKey(e) => write!(f, "Missing API key: {}", e),
+            ApiError::Unauthorized(e) => write!(f, "Unauthorized: {}", e),
+            ApiError::RateLimited(e) => write!(f, "Rate limited: {}", e),
+            ApiError::ModelUnavailable(e) => write!(f, "Model unavailable: {}", e),
+            ApiError::Truncated => {
+                write!(
+                    f,
+                    "The answer was cut off at the model's output token limit"
+                )
+            }
         }
     }
 }
 
 impl std::error::Error for ApiError {}
 
-async fn make_api_request(
+impl ApiError {
+    /// A stable identifier for the kind of error, for machine-readable output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::RequestFailed(e) if e.is_timeout() => "timeout",
+            ApiError::RequestFailed(_) => "network",
+            ApiError::ResponseParseFailed(_) => "invalid_response",
+            ApiError::ApiErrorResponse(_) => "api_error",
+            ApiError::RetryExhausted => "retry_exhausted",
+            ApiError::Cancelled => "cancelled",
+            ApiError::MissingApiKey(_) => "missing_api_key",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::ModelUnavailable(_) => "model_unavailable",
+            ApiError::Truncated => "truncated",
+        }
+    }
+}
+
+/// The result of a chat completion. `model` is the model that actually
+/// answered, as reported by the API.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Completion {
+    pub content: String,
+    pub model: Option<String>,
+    pub usage: Option<Usage>,
+    pub finish_reason: Option<String>,
+}
+
+/// Builds the HTTP client used for all API requests. `connect_timeout` bounds
+/// establishing the connection, `timeout` bounds the whole request.
+pub fn build_client(connect_timeout: Duration, timeout: Duration) -> Result<Client, ApiError> {
+    Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+        .build()
+        .map_err(ApiError::RequestFailed)
+}
+
+async fn post_chat_request(
     client: &Client,
     api_key: &str,
-    model: &str,
-    contents: &[String],
-    markdown: bool,
     base_url: Option<&str>,
-    preprocess: bool,
-) -> Result<String, ApiError> {
-    let url = base_url.unwrap_or("https://openrouter.ai/api/v1/chat/completions");
-    let messages = build_messages(contents, markdown, preprocess);
-    let request_body = serde_json::json!({
-        "model": model,
-        "messages": messages,
-    });
+    request_body: &serde_json::Value,
+) -> Result<reqwest::Response, ApiError> {
+    let url = base_url.unwrap_or(DEFAULT_CHAT_URL);
 
-    let response = client
+    let request = client
         .post(url)
         .header("Content-Type", "application/json")
         .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request_body)
-        .send()
+        .json(request_body)
+        .build()
+        .map_err(ApiError::RequestFailed)?;
+    trace!(
+        target: HTTP_TARGET,
+        "> POST {}\n{}\n\n{}",
+        url,
+        logging::format_headers(request.headers()),
+        request_body
+    );
+    let response = client
+        .execute(request)
         .await
         .map_err(ApiError::RequestFailed)?;
+    trace!(
+        target: HTTP_TARGET,
+        "< {}\n{}",
+        response.status(),
+        logging::format_headers(response.headers())
+    );
 
+    let status = response.status().as_u16();
     if !response.status().is_success() {
-        return Err(ApiError::ApiErrorResponse(
-            response.text().await.map_err(ApiError::RequestFailed)?,
-        ));
+        let body = response.text().await.map_err(ApiError::RequestFailed)?;
+        trace!(target: HTTP_TARGET, "< {}", body);
+        return Err(status_error(status, error_message(body)));
     }
 
-    let response_text = response.text().await.map_err(ApiError::RequestFailed)?;
-    let api_response: ApiResponse =
-        serde_json::from_str(&response_text).map_err(ApiError::ResponseParseFailed)?;
+    Ok(response)
+}
 
-    Ok(api_response
-        .choices
-        .get(0)
-        .map(|choice| choice.message.content.clone())
-        .unwrap_or_default())
+/// The error for an HTTP status, or for the `code` of an error sent inside a
+/// stream, which OpenRouter sets to the status it would have returned.
+fn status_error(status: u16, message: String) -> ApiError {
+    match status {
+        401 | 403 => ApiError::Unauthorized(message),
+        429 => ApiError::RateLimited(message),
+        404 | 502 | 503 => ApiError::ModelUnavailable(message),
+        _ => ApiError::ApiErrorResponse(message),
+    }
 }
 
-fn build_messages(contents: &[String], markdown: bool, preprocess: bool) -> Vec<serde_json::Value> {
-    if preprocess {
-        let mut messages = vec![serde_json::json!({
-            "role": "system",
-            "content": "Reformulate the user's submission into a clear and detailed instruction that captures the essence of what the user is asking for. Ensure that the reformulated instruction is phrased as if it were the user's original query or instruction, aiming to clarify any ambiguities and to provide a comprehensive understanding of the user's intent.  Prioritize maintaining all existing functionalities in the reformulated instruction unless the user explicitly requests the removal or modification of a specific feature. If necessary, add context or examples to enhance the clarity and specificity of the instruction.  If the user's submission is unclear or incomplete, attempt to infer the missing information or provide a helpful suggestion for clarification. Stay concise and avoid introducing unnecessary complexity or jargon, focusing on producing a reformulation that is easy to understand and actionable."
-        })];
+/// Extracts the message from an OpenRouter error body, falling back to the raw text.
+fn error_message(body: String) -> String {
+    match serde_json::from_str::<ErrorResponse>(&body) {
+        Ok(response) => format!("{} (code {})", response.error.message, response.error.code),
+        Err(_) => body,
+    }
+}
 
-        messages.extend(contents.iter().map(|content| {
-            serde_json::json!({
-                "role": "user",
-                "content": content
-            })
-        }));
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+    /// Sent instead of choices when the request fails after the stream started
+    #[serde(default)]
+    error: Option<ErrorDetails>,
+}
 
-        return messages;
-    };
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
 
-    let mut messages = vec![serde_json::json!({
-        "role": "system",
-        "content": "You are a helpful coding tool. You should keep
-    your answers brief, concise and mainly output code."
-    })];
+#[derive(Debug, Default, Deserialize)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+}
 
-    if markdown {
-        messages.push(serde_json::json!({
-            "role": "system",
-            "content": "Please format your responses using Markdown syntax
-  for better readability. Use appropriate Markdown elements for headers,
-  lists, code blocks, and emphasis where applicable."
-        }));
-    } else {
-        messages.push(serde_json::json!({
-            "role": "system",
-            "content": "Answer without using markdown formatting!"
-        }));
+async fn make_streaming_api_request(
+    client: &Client,
+    api_key: &str,
+    request: &ChatRequest,
+    base_url: Option<&str>,
+    on_delta: &mut (dyn FnMut(&str) + Send),
+) -> Result<Completion, ApiError> {
+    let mut messages = build_messages(&request.system, &request.history, &request.contents);
+    attach_images(&mut messages, &request.images);
+    let mut request_body = serde_json::json!({
+        "model": request.model,
+        "messages": messages,
+        "stream": true,
+        "stream_options": { "include_usage": true },
+    });
+    if let Some(temperature) = request.temperature {
+        request_body["temperature"] = serde_json::json!(temperature);
+    }
+    if let Some(response_format) = &request.response_format {
+        request_body["response_format"] = response_format.clone();
+    }
+
+    let mut response = post_chat_request(client, api_key, base_url, &request_body).await?;
+
+    let mut completion = Completion::default();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(ApiError::RequestFailed)? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if !line.trim().is_empty() {
+                trace!(target: HTTP_TARGET, "< {}", line.trim());
+            }
+            match parse_sse_line(line.trim())? {
+                SseLine::Chunk(chunk) => apply_chunk(&mut completion, chunk, on_delta)?,
+                SseLine::Done => return Ok(completion),
+                SseLine::Skip => {}
+            }
+        }
+    }
+
+    Ok(completion)
+}
+
+fn apply_chunk(
+    completion: &mut Completion,
+    chunk: StreamChunk,
+    on_delta: &mut (dyn FnMut(&str) + Send),
+) -> Result<(), ApiError> {
+    if let Some(error) = chunk.error {
+        let message = format!("{} (code {})", error.message, error.code);
+        return Err(status_error(error.code.try_into().unwrap_or(0), message));
+    }
+    if chunk.model.is_some() {
+        completion.model = chunk.model;
+    }
+    if chunk.usage.is_some() {
+        completion.usage = chunk.usage;
+    }
+    if let Some(choice) = chunk.choices.into_iter().next() {
+        if choice.finish_reason.is_some() {
+            completion.finish_reason = choice.finish_reason;
+        }
+        if let Some(delta) = choice.delta.content.filter(|delta| !delta.is_empty()) {
+            on_delta(&delta);
+            completion.content.push_str(&delta);
+        }
+    }
+    Ok(())
+}
+
+enum SseLine {
+    Chunk(StreamChunk),
+    Done,
+    Skip,
+}
+
+/// Interprets one line of a server-sent event stream. Comments (`: ...`) and
+/// blank lines are skipped.
+fn parse_sse_line(line: &str) -> Result<SseLine, ApiError> {
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(SseLine::Skip);
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Ok(SseLine::Done);
     }
 
-    messages.extend(contents.iter().map(|content| {
+    serde_json::from_str(data)
+        .map(SseLine::Chunk)
+        .map_err(ApiError::ResponseParseFailed)
+}
+
+/// Assembles the message list: each system prompt in order, then one user
+/// message per content entry.
+pub fn build_messages(
+    system: &[String],
+    history: &[Turn],
+    contents: &[String],
+) -> Vec<serde_json::Value> {
+    let system = system.iter().map(|prompt| {
+        serde_json::json!({
+            "role": "system",
+            "content": prompt
+        })
+    });
+    let history = history.iter().map(|turn| {
+        serde_json::json!({
+            "role": turn.role.as_str(),
+            "content": turn.content
+        })
+    });
+    let user = contents.iter().map(|content| {
         serde_json::json!({
             "role": "user",
             "content": content
         })
+    });
+
+    system.chain(history).chain(user).collect()
+}
+
+/// Turns the last user message into a content-part array holding its text
+/// followed by `images`. Without a user message, one is added for the images.
+pub fn attach_images(messages: &mut Vec<serde_json::Value>, images: &[Image]) {
+    if images.is_empty() {
+        return;
+    }
+
+    let mut parts = Vec::new();
+    match messages.last_mut() {
+        Some(message) if message["role"] == "user" => {
+            let text = message["content"].as_str().unwrap_or_default().to_string();
+            parts.push(ContentPart::Text { text });
+        }
+        _ => messages.push(serde_json::json!({ "role": "user" })),
+    }
+    parts.extend(images.iter().map(|image| ContentPart::ImageUrl {
+        image_url: ImageUrl {
+            url: image.data_url(),
+        },
     }));
 
-    messages
+    messages.last_mut().unwrap()["content"] = serde_json::json!(parts);
 }
 
-pub async fn send_api_request(
+/// Streams a completion, calling `on_delta` with each piece of content as it
+/// arrives, and returns the full response. Failed attempts are only retried
+/// while nothing has been streamed yet, so `on_delta` never sees duplicates.
+pub async fn stream_api_request(
     client: &Client,
     api_key: &str,
-    model: &str,
-    contents: &[String],
-    markdown: bool,
+    request: &ChatRequest,
     base_url: Option<&str>,
-    preprocess: bool,
-) -> Result<String, ApiError> {
+    on_delta: &mut (dyn FnMut(&str) + Send),
+) -> Result<Completion, ApiError> {
     let max_retries = 3;
     let initial_delay = Duration::from_millis(100);
 
-    for attempt in 0..max_retries {
-        match make_api_request(
-            client, api_key, model, contents, markdown, base_url, preprocess,
-        )
-        .await
-        {
-            Ok(response) => return Ok(response),
-            Err(_e) if attempt < max_retries - 1 => {
-                tokio::time::sleep(initial_delay * 2u32.pow(attempt as u32)).await;
+    async {
+        for attempt in 0..max_retries {
+            let mut streamed = false;
+            let mut forward = |delta: &str| {
+                streamed = true;
+                on_delta(delta);
+            };
+            let result =
+                make_streaming_api_request(client, api_key, request, base_url, &mut forward)
+                    .instrument(tracing::debug_span!("attempt", number = attempt + 1))
+                    .await;
+            match result {
+                Ok(response) => {
+                    debug!(
+                        finish_reason = ?response.finish_reason,
+                        usage = ?response.usage,
+                        "Completed"
+                    );
+                    return Ok(response);
+                }
+                Err(e) if !streamed && attempt < max_retries - 1 => {
+                    let delay = initial_delay * 2u32.pow(attempt as u32);
+                    info!(
+                        "Attempt {} failed ({}), retrying in {:?}",
+                        attempt + 1,
+                        e,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
             }
-            Err(e) => return Err(e),
         }
+        Err(ApiError::RetryExhausted)
     }
-    Err(ApiError::RetryExhausted)
+    .instrument(tracing::info_span!("request", model = %request.model))
+    .await
+}
+
+/// Where the API key is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeySource {
+    /// An environment variable holding the key
+    Env(String),
+    /// A file whose (trimmed) contents are the key
+    File(PathBuf),
+    /// A shell command printing the key, e.g. `pass show openrouter`
+    Command(String),
 }
 
-pub fn get_api_key() -> String {
-    env::var("OPENROUTER_API_KEY").expect("OPENROUTER_API_KEY not set")
+impl ApiKeySource {
+    pub fn resolve(&self) -> Result<String, ApiError> {
+        let key = match self {
+            ApiKeySource::Env(name) => env::var(name).map_err(|_| {
+                ApiError::MissingApiKey(format!("environment variable {} is not set", name))
+            })?,
+            ApiKeySource::File(path) => fs::read_to_string(path).map_err(|e| {
+                ApiError::MissingApiKey(format!("could not read {}: {}", path.display(), e))
+            })?,
+            ApiKeySource::Command(command) => run_key_command(command)?,
+        };
+
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(ApiError::MissingApiKey(format!("{} is empty", self)));
+        }
+        Ok(key.to_string())
+    }
+}
+
+impl std::fmt::Display for ApiKeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeySource::Env(name) => write!(f, "environment variable {}", name),
+            ApiKeySource::File(path) => write!(f, "{}", path.display()),
+            ApiKeySource::Command(command) => write!(f, "output of `{}`", command),
+        }
+    }
+}
+
+fn run_key_command(command: &str) -> Result<String, ApiError> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| ApiError::MissingApiKey(format!("could not run `{}`: {}", command, e)))?;
+
+    if !output.status.success() {
+        return Err(ApiError::MissingApiKey(format!(
+            "`{}` failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
 }
@@ -0,0 +1,95 @@
+use crate::api::ApiError;
+use crate::context::{self, Family};
+use crate::prompts;
+use crate::provider::{ChatRequest, Provider};
+use futures::stream::{self, StreamExt};
+use std::io::Write;
+
+pub const DEFAULT_CHUNK_TOKENS: usize = 6000;
+pub const DEFAULT_PARALLELISM: usize = 4;
+
+/// How `--chunked` splits the input and processes the parts.
+#[derive(Debug, Clone)]
+pub struct ChunkOptions {
+    /// Model that condenses each chunk
+    pub model: String,
+    /// Upper bound of a chunk, in estimated tokens of `model`
+    pub chunk_tokens: usize,
+    /// How many chunks are processed at the same time
+    pub parallelism: usize,
+}
+
+/// Splits the largest entry of `contents` into chunks, has `options.model`
+/// take notes on each one concurrently and returns the contents with that
+/// entry replaced by the notes, ready for the reduce request. Returns `None`
+/// when the input fits in a single chunk. Progress is written to `progress`.
+pub async fn map_chunks(
+    provider: &dyn Provider,
+    contents: &[String],
+    options: &ChunkOptions,
+    progress: &mut (dyn Write + Send),
+) -> Result<Option<Vec<String>>, ApiError> {
+    let Some(index) = (0..contents.len()).max_by_key(|&i| contents[i].len()) else {
+        return Ok(None);
+    };
+    let family = Family::of(&options.model);
+    let chunks = context::chunk(&contents[index], family, options.chunk_tokens);
+    if chunks.len() < 2 {
+        return Ok(None);
+    }
+
+    let request_text = contents
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, content)| content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let request_text = if request_text.trim().is_empty() {
+        "Summarize the input."
+    } else {
+        request_text.as_str()
+    };
+
+    let parts = chunks.len();
+    let requests: Vec<ChatRequest> = chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| ChatRequest {
+            model: options.model.clone(),
+            system: vec![prompts::CHUNK_MAP
+                .replace("{part}", &(i + 1).to_string())
+                .replace("{parts}", &parts.to_string())
+                .replace("{request}", request_text)],
+            contents: vec![chunk],
+            ..ChatRequest::default()
+        })
+        .collect();
+
+    let mut results = stream::iter(requests.iter().enumerate())
+        .map(|(i, request)| async move {
+            let completion = provider.complete(request, &mut |_: &str| {}).await;
+            (i, completion)
+        })
+        .buffer_unordered(options.parallelism.max(1));
+
+    let mut notes = vec![String::new(); parts];
+    let mut done = 0;
+    let _ = writeln!(progress, "Split the input into {} chunks", parts);
+    while let Some((i, completion)) = results.next().await {
+        notes[i] = completion?.content;
+        done += 1;
+        let _ = writeln!(progress, "Processed chunk {}/{}", done, parts);
+    }
+
+    let notes = notes
+        .iter()
+        .enumerate()
+        .map(|(i, note)| format!("## Notes on part {} of {}\n\n{}", i + 1, parts, note.trim()))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let mut contents = contents.to_vec();
+    contents[index] = notes;
+
+    Ok(Some(contents))
+}
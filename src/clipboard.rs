@@ -0,0 +1,147 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::env;
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Commands that print the clipboard contents, tried in order.
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+    &["pbpaste"],
+];
+
+/// Commands that set the clipboard from stdin, tried in order.
+const COPY_COMMANDS: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["pbcopy"],
+];
+
+/// Reads the clipboard with the first available paste command.
+pub fn read() -> io::Result<String> {
+    Clipboard::from_env().read()
+}
+
+/// Places `text` on the clipboard, using OSC 52 over SSH or when no copy
+/// command is installed.
+pub fn write(text: &str) -> io::Result<()> {
+    Clipboard::from_env().write(text, &mut terminal())
+}
+
+/// Access to the system clipboard through the usual command-line tools.
+pub struct Clipboard {
+    path: Option<OsString>,
+    remote: bool,
+}
+
+impl Clipboard {
+    /// Uses `$PATH` and treats the session as remote when `$SSH_TTY` or
+    /// `$SSH_CONNECTION` is set, since the tools would then reach the remote
+    /// machine's clipboard rather than the user's.
+    pub fn from_env() -> Self {
+        Clipboard {
+            path: None,
+            remote: env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some(),
+        }
+    }
+
+    /// Looks the tools up in `path` instead of `$PATH`.
+    pub fn with_path(mut self, path: impl Into<OsString>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_remote(mut self, remote: bool) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(args[0]);
+        command.args(&args[1..]);
+        if let Some(path) = &self.path {
+            command.env("PATH", path);
+        }
+        command
+    }
+
+    pub fn read(&self) -> io::Result<String> {
+        for args in PASTE_COMMANDS {
+            match self.command(args).output() {
+                Ok(output) if output.status.success() => {
+                    return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+                }
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no clipboard tool found (tried wl-paste, xclip, xsel, pbpaste)",
+        ))
+    }
+
+    /// Copies `text` with the first working copy command. In a remote session,
+    /// or when none is installed, the OSC 52 sequence is written to `terminal`
+    /// instead.
+    pub fn write(&self, text: &str, terminal: &mut dyn Write) -> io::Result<()> {
+        if self.remote {
+            return write_osc52(text, terminal);
+        }
+
+        for args in COPY_COMMANDS {
+            let mut child = match self
+                .command(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+            {
+                Ok(child) => child,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            // A tool that exits without reading closes the pipe; its exit
+            // status below decides whether to try the next one.
+            if let Some(mut stdin) = child.stdin.take() {
+                match stdin.write_all(text.as_bytes()) {
+                    Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+                    _ => {}
+                }
+            }
+            if child.wait()?.success() {
+                return Ok(());
+            }
+        }
+
+        write_osc52(text, terminal)
+    }
+}
+
+/// Writes the OSC 52 escape sequence that asks the terminal emulator to set
+/// its clipboard, wrapped for tmux when running inside it.
+pub fn write_osc52(text: &str, terminal: &mut dyn Write) -> io::Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+    if env::var_os("TMUX").is_some() {
+        write!(terminal, "\x1bPtmux;\x1b{}\x1b\\", sequence)?;
+    } else {
+        write!(terminal, "{}", sequence)?;
+    }
+    terminal.flush()
+}
+
+/// The controlling terminal, so escape sequences bypass redirected output.
+fn terminal() -> Box<dyn Write> {
+    match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(tty) => Box::new(tty),
+        Err(_) => Box::new(io::stderr()),
+    }
+}
@@ -1,12 +1,45 @@
 use crate::api;
+use crate::api::{ApiError, ApiKeySource, Completion};
+use crate::chunked::{self, ChunkOptions};
+use crate::clipboard;
+use crate::completions;
+use crate::config::{Config, StepConfig};
+use crate::context::{self, Cut, Family, Overflow};
+use crate::history::{self, Entry, History, RunOptions};
+use crate::image::{Image, ImageError};
 use crate::input;
-use crate::input::InputResult;
 use crate::input::RealEditor;
 use crate::input::RealStdin;
 use crate::input::StdinReader;
+use crate::input::{EditorTemplate, InputResult};
+use crate::interrupt;
 use crate::markdown;
+use crate::models::{self, Catalog, ListFormat, ModelCache};
+use crate::output::{Event, OutputFormat, Report};
+use crate::pipeline::{self, Pipeline, PipelineError, Refine};
+use crate::prompts::{self, SystemPrompts};
+use crate::provider::{ChatRequest, OpenRouterProvider, Provider, RoutingProvider};
+use crate::redact::Redactor;
+use crate::schema::{Schema, SchemaError};
+use crate::server::{self, ServerOptions};
+use crate::template::{self, Template};
+use crate::terminal::{ColorChoice, Display};
 use atty::Stream;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use clap_complete::engine::{ArgValueCandidates, ArgValueCompleter};
+use clap_complete::Shell;
+use regex::RegexBuilder;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_MODEL: &str = "anthropic/claude-3.5-sonnet";
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_SCHEMA_RETRIES: u32 = 2;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -15,125 +48,1320 @@ pub struct Cli {
     #[arg(required = false)]
     pub context: Vec<String>,
 
-    /// Model to use for the API request
-    #[arg(short, long, default_value = "anthropic/claude-3.5-sonnet")]
-    pub model: String,
+    /// Model or alias to use, or a comma-separated list to fall back through [default: anthropic/claude-3.5-sonnet]
+    #[arg(short, long, add = ArgValueCompleter::new(completions::complete_model))]
+    pub model: Option<String>,
 
     /// Model to use for the simpler thinking
-    #[arg(short, long, default_value = "meta-llama/llama-3.1-405b")]
+    #[arg(
+        short,
+        long,
+        default_value = "meta-llama/llama-3.1-405b",
+        add = ArgValueCompleter::new(completions::complete_model)
+    )]
     pub cheap_model: String,
 
-    /// Enable debug output
+    /// Log debug output to stderr, same as -vv
     #[arg(long, default_value = "false")]
     pub debug: bool,
 
-    /// Use Markdown rendering
+    /// Print only the answer: no progress, notices or warnings on stderr, only errors
+    #[arg(short, long, default_value = "false", conflicts_with_all = ["debug", "verbose"])]
+    pub quiet: bool,
+
+    /// Log more to stderr: -v for info, -vv for debug, -vvv for trace
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Write the log to this file, rotated daily, instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Log HTTP requests and responses with their bodies; the Authorization header is redacted
+    #[arg(long, default_value = "false")]
+    pub log_http: bool,
+
+    /// Use Markdown rendering; plain text when stdout is not a terminal
     #[arg(long, default_value = "false")]
     pub markdown: bool,
 
+    /// When to color the output; `auto` follows NO_COLOR and CLICOLOR_FORCE
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Wrap Markdown at this many columns [default: the terminal width]
+    #[arg(long, value_name = "COLS", value_parser = clap::value_parser!(u16).range(1..))]
+    pub width: Option<u16>,
+
+    /// Print long answers directly instead of through $PAGER
+    #[arg(long, default_value = "false")]
+    pub no_pager: bool,
+
+    /// Whether stdout is a terminal, set by `run` rather than a flag
+    #[arg(skip)]
+    pub stdout_tty: bool,
+
+    /// The cached model catalog of the API in use, loaded by `run`
+    #[arg(skip)]
+    pub catalog: Option<Catalog>,
+
     /// Enable pre-processing mode
     #[arg(long, default_value = "false")]
     pub preprocess: bool,
+
+    /// Seconds to wait for a connection to the API [default: 10]
+    #[arg(long, value_name = "SECS")]
+    pub connect_timeout: Option<u64>,
+
+    /// Seconds to wait for a whole request to complete [default: 300]
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+
+    /// Root URL of the OpenAI-compatible API [default: https://openrouter.ai/api/v1]
+    #[arg(long, value_name = "URL")]
+    pub base_url: Option<String>,
+
+    /// Environment variable holding the API key [default: OPENROUTER_API_KEY]
+    #[arg(long, value_name = "VAR")]
+    pub api_key_env: Option<String>,
+
+    /// File containing the API key
+    #[arg(long, value_name = "PATH", conflicts_with = "api_key_env")]
+    pub api_key_file: Option<PathBuf>,
+
+    /// Send the input as-is, without redacting secrets
+    #[arg(long, default_value = "false")]
+    pub no_redact: bool,
+
+    /// Prompt template to use, from the templates directory
+    #[arg(
+        short,
+        long,
+        value_name = "NAME",
+        add = ArgValueCandidates::new(completions::template_candidates)
+    )]
+    pub template: Option<String>,
+
+    /// Set a template variable, can be repeated
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, String)>,
+
+    /// System prompt to use instead of the default, or @FILE to read it from a file
+    #[arg(long, value_name = "TEXT|@FILE", conflicts_with_all = ["persona", "no_system"])]
+    pub system: Option<String>,
+
+    /// Named persona to use as the system prompt: reviewer, shell, explainer or one from the config
+    #[arg(long, value_name = "NAME", conflicts_with = "no_system")]
+    pub persona: Option<String>,
+
+    /// Send only the user's messages, without any system prompts
+    #[arg(long, default_value = "false")]
+    pub no_system: bool,
+
+    /// Output format for the answer
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Image to send along with the prompt, can be repeated
+    #[arg(long = "image", value_name = "PATH")]
+    pub images: Vec<PathBuf>,
+
+    /// Read input from the clipboard, in addition to arguments and stdin
+    #[arg(long, default_value = "false")]
+    pub from_clipboard: bool,
+
+    /// Copy the response to the clipboard
+    #[arg(long, default_value = "false")]
+    pub copy: bool,
+
+    /// With --copy, copy only the code blocks of the response
+    #[arg(long, default_value = "false", requires = "copy")]
+    pub code_only: bool,
+
+    /// Split long input into chunks, condense them on the cheap model, then answer from the notes
+    #[arg(long, default_value = "false")]
+    pub chunked: bool,
+
+    /// Maximum size of a --chunked chunk in tokens [default: 6000]
+    #[arg(long, value_name = "TOKENS", requires = "chunked")]
+    pub chunk_tokens: Option<usize>,
+
+    /// How many --chunked chunks to process at the same time [default: 4]
+    #[arg(long, value_name = "N", requires = "chunked")]
+    pub parallel: Option<usize>,
+
+    /// What to do when the input does not fit in the model's context window
+    #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = Overflow::Error)]
+    pub overflow: Overflow,
+
+    /// Context window of the model in tokens, for models missing from the built-in table
+    #[arg(long, value_name = "TOKENS")]
+    pub context_window: Option<usize>,
+
+    /// Do not record this run in the prompt history
+    #[arg(long, default_value = "false")]
+    pub private: bool,
+
+    /// Have a critic review the answer and the model revise it, up to N times
+    #[arg(long, value_name = "N", conflicts_with = "schema")]
+    pub refine: Option<u32>,
+
+    /// Model that critiques answers for --refine [default: the cheap model]
+    #[arg(
+        long,
+        value_name = "MODEL",
+        requires = "refine",
+        add = ArgValueCompleter::new(completions::complete_model)
+    )]
+    pub critic_model: Option<String>,
+
+    /// JSON Schema file the answer must match; the validated JSON is printed
+    #[arg(long, value_name = "PATH")]
+    pub schema: Option<PathBuf>,
+
+    /// How many times to ask again when the answer does not match --schema [default: 2]
+    #[arg(long, value_name = "N", requires = "schema")]
+    pub schema_retries: Option<u32>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Generate a shell command and optionally run it
+    Sh {
+        /// What the command should do
+        #[arg(required = true)]
+        request: Vec<String>,
+    },
+    /// Run a pipeline from the config, or list them without a name
+    Pipe {
+        /// Name of the pipeline
+        #[arg(add = ArgValueCandidates::new(completions::pipeline_candidates))]
+        name: Option<String>,
+
+        /// Input for the first steps, in addition to stdin
+        input: Vec<String>,
+    },
+    /// List the models of the provider, with context length, pricing and modality
+    Models {
+        /// Only list models whose id contains this text
+        #[arg(long, value_name = "TEXT")]
+        search: Option<String>,
+
+        /// Fetch the list again instead of using the cached one
+        #[arg(long, default_value = "false")]
+        refresh: bool,
+
+        /// Print only the model ids, one per line
+        #[arg(long, default_value = "false")]
+        ids: bool,
+    },
+    /// Serve an OpenAI-compatible API on localhost, answered through mergil
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+
+        /// Address to bind to
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+    },
+    /// List, search and re-run past prompts
+    History {
+        /// Only list prompts or answers matching this regex (case-insensitive)
+        #[arg(long, value_name = "PATTERN")]
+        grep: Option<String>,
+
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+    },
+    /// Manage prompt templates
+    Templates {
+        #[command(subcommand)]
+        command: TemplatesCommand,
+    },
+    /// Print the script that sets up completions for a shell
+    Completions {
+        /// Shell to complete in
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print the man page in roff format
+    Man,
+}
+
+#[derive(Subcommand)]
+pub enum HistoryCommand {
+    /// Print a past prompt and its answer
+    Show {
+        /// Id of the history entry
+        id: u64,
+    },
+    /// Send a past prompt again
+    Rerun {
+        /// Id of the history entry
+        id: u64,
+
+        /// Model to use instead of the one the prompt was sent to
+        #[arg(short, long, add = ArgValueCompleter::new(completions::complete_model))]
+        model: Option<String>,
+    },
 }
 
-pub async fn handle_input(cli: &Cli) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+#[derive(Subcommand)]
+pub enum TemplatesCommand {
+    /// List installed templates
+    List,
+}
+
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", s))
+}
+
+/// The models to try in order: `--model` (comma-separated), else the
+/// template's model, else `model` from the config, else the default. Aliases
+/// are resolved.
+pub fn model_chain(cli: &Cli, config: &Config, template_model: Option<&str>) -> Vec<String> {
+    let models = match (&cli.model, template_model, &config.model) {
+        (Some(model), _, _) => model.split(',').map(|m| m.trim().to_string()).collect(),
+        (None, Some(model), _) => vec![model.to_string()],
+        (None, None, Some(spec)) => spec.models(),
+        (None, None, None) => vec![DEFAULT_MODEL.to_string()],
+    };
+    let chain: Vec<String> = models
+        .iter()
+        .filter(|m| !m.is_empty())
+        .map(|m| config.resolve_alias(m))
+        .collect();
+    if chain.is_empty() {
+        vec![DEFAULT_MODEL.to_string()]
+    } else {
+        chain
+    }
+}
+
+/// Resolves aliases given for `--cheap-model` and `--critic-model`.
+pub fn resolve_aliases(cli: Cli, config: &Config) -> Cli {
+    Cli {
+        cheap_model: config.resolve_alias(&cli.cheap_model),
+        critic_model: cli.critic_model.as_deref().map(|m| config.resolve_alias(m)),
+        ..cli
+    }
+}
+
+pub async fn handle_input(
+    cli: &Cli,
+    config: &Config,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     let mut contents = if !cli.context.is_empty() {
         vec![cli.context.join(" ")]
     } else {
         Vec::new()
     };
 
+    let mut piped = None;
     if !atty::is(Stream::Stdin) {
         let mut piped_input = String::new();
         RealStdin.read_to_string(&mut piped_input)?;
         if !piped_input.trim().is_empty() {
-            contents.push(piped_input);
+            contents.push(piped_input.clone());
+            piped = Some(piped_input);
         }
     }
 
-    if cli.context.is_empty() && std::env::var("NO_EDITOR").is_err() {
+    if cli.from_clipboard {
+        let clipboard = clipboard::read()?;
+        if !clipboard.trim().is_empty() {
+            contents.push(clipboard);
+        }
+    }
+
+    // Pipelines take piped input as it is, without an editor to add a request.
+    let piped_pipe = piped.is_some() && matches!(cli.command, Some(Command::Pipe { .. }));
+    if cli.context.is_empty()
+        && !cli.from_clipboard
+        && !piped_pipe
+        && std::env::var("NO_EDITOR").is_err()
+    {
+        let template = EditorTemplate {
+            model: model_chain(cli, config, None).into_iter().next(),
+            files: cli.images.clone(),
+            stdin: piped,
+            last_reply: History::from_config(config)
+                .and_then(|history| history.latest().ok().flatten())
+                .map(|entry| entry.answer),
+            comment_prefix: config.editor.comment_prefix.clone(),
+        };
         let mut real_stdin = RealStdin;
-        match input::get_input(true, &mut real_stdin, &RealEditor)? {
+        match input::get_input_with_template(true, &mut real_stdin, &RealEditor, &template)? {
             InputResult::Content(content) => contents.push(content),
-            InputResult::Cancelled => {
-                if cli.debug {
-                    println!("Operation cancelled.");
-                }
-            }
+            InputResult::Cancelled => debug!("Operation cancelled."),
         }
     }
 
     Ok(contents)
 }
 
+/// Builds the OpenRouter provider from the command line and config file, with
+/// a route to each of the configured `[providers]`.
+pub fn build_provider(
+    cli: &Cli,
+    config: &Config,
+) -> Result<RoutingProvider<OpenRouterProvider>, Box<dyn std::error::Error>> {
+    let client = http_client(cli, config)?;
+    let api_key = api_key_source(cli, config).resolve()?;
+    let base_url = cli.base_url.clone().or_else(|| config.base_url.clone());
+
+    let mut provider =
+        RoutingProvider::new(OpenRouterProvider::new(client.clone(), api_key, base_url));
+    for (name, route) in &config.providers {
+        let api_key = match &route.api_key_env {
+            Some(env) => ApiKeySource::Env(env.clone()).resolve()?,
+            None => String::new(),
+        };
+        let base_url = Some(route.base_url.clone());
+        provider = provider.with_route(
+            name,
+            OpenRouterProvider::new(client.clone(), api_key, base_url),
+        );
+    }
+    Ok(provider)
+}
+
+/// The HTTP client for API requests, with the connect and total timeouts from
+/// the command line or config file.
+pub fn http_client(cli: &Cli, config: &Config) -> Result<reqwest::Client, ApiError> {
+    api::build_client(
+        Duration::from_secs(
+            cli.connect_timeout
+                .or(config.connect_timeout)
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        ),
+        Duration::from_secs(
+            cli.timeout
+                .or(config.timeout)
+                .unwrap_or(DEFAULT_TIMEOUT_SECS),
+        ),
+    )
+}
+
+/// The API root from the command line or config file.
+fn base_url(cli: &Cli, config: &Config) -> String {
+    cli.base_url
+        .clone()
+        .or_else(|| config.base_url.clone())
+        .unwrap_or_else(|| api::DEFAULT_BASE_URL.to_string())
+}
+
+/// Picks the API key source: flags first, then the config file, then the
+/// default environment variable.
+pub fn api_key_source(cli: &Cli, config: &Config) -> ApiKeySource {
+    if let Some(path) = &cli.api_key_file {
+        ApiKeySource::File(path.clone())
+    } else if let Some(name) = &cli.api_key_env {
+        ApiKeySource::Env(name.clone())
+    } else if let Some(command) = &config.api_key_cmd {
+        ApiKeySource::Command(command.clone())
+    } else if let Some(path) = &config.api_key_file {
+        ApiKeySource::File(path.clone())
+    } else {
+        ApiKeySource::Env(
+            config
+                .api_key_env
+                .clone()
+                .unwrap_or_else(|| api::DEFAULT_API_KEY_ENV.to_string()),
+        )
+    }
+}
+
+/// A unique id for this invocation, reported by the JSON output modes.
+pub fn new_session_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", nanos, std::process::id())
+}
+
 pub async fn process_contents(
     cli: &Cli,
+    config: &Config,
     contents: &[String],
+    provider: &dyn Provider,
+    out: &mut (dyn Write + Send),
+) -> Result<Option<Report>, Box<dyn std::error::Error>> {
+    let session_id = new_session_id();
+
+    let template = match &cli.template {
+        Some(name) => {
+            let dir = template::templates_dir(config).ok_or("No templates directory found")?;
+            Some(Template::load(&dir, name)?)
+        }
+        None => None,
+    };
+
+    let schema = cli.schema.as_deref().map(Schema::load).transpose()?;
+
+    let images = cli
+        .images
+        .iter()
+        .map(|path| Image::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let prompts = if cli.no_system {
+        SystemPrompts::none()
+    } else {
+        SystemPrompts::resolve(cli.system.as_deref(), cli.persona.as_deref(), config)?
+    };
+
+    let markdown = cli.markdown || template.as_ref().and_then(|t| t.markdown) == Some(true);
+    let request = ChatRequest {
+        model: model_chain(
+            cli,
+            config,
+            template.as_ref().and_then(|t| t.model.as_deref()),
+        )
+        .remove(0),
+        temperature: template.as_ref().and_then(|t| t.temperature),
+        images,
+        ..ChatRequest::default()
+    };
+
+    // A text-only model in the cached catalog fails here rather than with
+    // an unclear error from the API.
+    if !request.images.is_empty() {
+        let text_only = cli
+            .catalog
+            .as_ref()
+            .and_then(|catalog| catalog.accepts_images(&request.model))
+            == Some(false);
+        if text_only || !provider.supports_images() {
+            return Err(ImageError::UnsupportedProvider.into());
+        }
+    }
+
+    debug!(model = %request.model, markdown, "Preparing request");
+    for (i, content) in contents.iter().enumerate() {
+        debug!("Input {}: {}", i + 1, content);
+    }
+    for image in &request.images {
+        debug!("Image: {} ({})", image.path.display(), image.mime);
+    }
+
+    if contents.is_empty() && template.is_none() && request.images.is_empty() {
+        info!("No input provided. Exiting.");
+        return Ok(None);
+    }
+
+    let contents = match &template {
+        Some(template) => {
+            let vars: HashMap<String, String> = cli.vars.iter().cloned().collect();
+            let rendered = template.render(&contents.join("\n\n"), &vars)?;
+            if template.uses_input() {
+                vec![rendered]
+            } else {
+                std::iter::once(rendered)
+                    .chain(contents.iter().cloned())
+                    .collect()
+            }
+        }
+        None => contents.to_vec(),
+    };
+
+    let mut input_contents = if cli.no_redact {
+        contents
+    } else {
+        let redactor = Redactor::new(&config.redact.patterns)?;
+        let (redacted, redactions) = redactor.redact_all(&contents);
+        for redaction in &redactions {
+            warn!("Redacted {}: {}", redaction.kind, redaction.count);
+        }
+        redacted
+    };
+
+    let mut system = prompts.messages(markdown);
+    if cli.chunked {
+        if let Some(notes) = map_chunks(cli, provider, &input_contents).await? {
+            input_contents = notes;
+            system.push(prompts::CHUNK_REDUCE.to_string());
+        }
+    }
+
+    fit_context(cli, provider, &request.model, &system, &mut input_contents).await?;
+
+    if cli.preprocess {
+        let steps = pipeline::preprocess_steps(&prompts);
+        let mut progress = io::sink();
+        let run = pipeline::run_steps(
+            provider,
+            &steps,
+            &input_contents,
+            &request.model,
+            &mut progress,
+        );
+        let preprocessed_message = interrupt::interruptible(run)
+            .await
+            .ok_or(ApiError::Cancelled)??;
+        debug!("Preprocessed message: {}", preprocessed_message);
+        // Replace the last message with the preprocessed message
+        if !input_contents.is_empty() {
+            *input_contents.last_mut().unwrap() = preprocessed_message;
+        } else {
+            input_contents.push(preprocessed_message);
+        }
+    }
+
+    let request = ChatRequest {
+        system,
+        contents: input_contents,
+        ..request
+    };
+    log_messages(&request)?;
+    let started = Instant::now();
+    let completion = match &schema {
+        Some(schema) => {
+            let retries = cli.schema_retries.unwrap_or(DEFAULT_SCHEMA_RETRIES);
+            complete_with_schema(provider, request.clone(), schema, retries).await?
+        }
+        None => match cli.refine.filter(|&rounds| rounds > 0) {
+            Some(rounds) => {
+                let initial = complete_with_cancellation(
+                    provider,
+                    &request,
+                    OutputFormat::Text,
+                    &mut io::sink(),
+                )
+                .await?;
+                let critic = cli.critic_model.as_ref().unwrap_or(&cli.cheap_model);
+                let pipeline = Pipeline::new().then(Refine::new(critic, rounds));
+                interrupt::interruptible(pipeline.apply(provider, &request, initial))
+                    .await
+                    .ok_or(ApiError::Cancelled)??
+            }
+            None => complete_with_cancellation(provider, &request, cli.output, out).await?,
+        },
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let report = Report::new(completion, latency_ms, &session_id);
+    match cli.output {
+        OutputFormat::Text => {
+            display(cli).print(&report.content, markdown && schema.is_none(), out)?;
+        }
+        OutputFormat::Json => {
+            writeln!(out, "{}", serde_json::to_string(&report)?)?;
+        }
+        OutputFormat::Ndjson => {
+            writeln!(out, "{}", serde_json::to_string(&Event::Done(&report))?)?;
+        }
+    }
+
+    Ok(Some(report))
+}
+
+/// Runs the map step of `--chunked` on the cheap model, with progress on
+/// stderr. Chunks are kept within the cheap model's context window.
+async fn map_chunks(
+    cli: &Cli,
+    provider: &dyn Provider,
+    contents: &[String],
+) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
+    let mut chunk_tokens = cli.chunk_tokens.unwrap_or(chunked::DEFAULT_CHUNK_TOKENS);
+    if let Some(window) = known_context_window(cli, &cli.cheap_model) {
+        let map_prompt = vec![prompts::CHUNK_MAP.to_string()];
+        let family = Family::of(&cli.cheap_model);
+        chunk_tokens = chunk_tokens.min(context::input_budget(family, window, &map_prompt));
+    }
+    let options = ChunkOptions {
+        model: cli.cheap_model.clone(),
+        chunk_tokens,
+        parallelism: cli.parallel.unwrap_or(chunked::DEFAULT_PARALLELISM),
+    };
+
+    let mut progress = progress(cli);
+    let map = chunked::map_chunks(provider, contents, &options, &mut *progress);
+    Ok(interrupt::interruptible(map)
+        .await
+        .ok_or(ApiError::Cancelled)??)
+}
+
+/// Where progress and notices go: stderr, or nowhere with `--quiet`.
+fn progress(cli: &Cli) -> Box<dyn Write + Send> {
+    if cli.quiet {
+        Box::new(io::sink())
+    } else {
+        Box::new(io::stderr())
+    }
+}
+
+/// Fails with `ApiError::Truncated` when the answer hit the output token
+/// limit. Called after the answer was written, so it is not lost.
+pub fn check_finished(report: &Report) -> Result<(), ApiError> {
+    match report.finish_reason.as_deref() {
+        Some("length") => Err(ApiError::Truncated),
+        _ => Ok(()),
+    }
+}
+
+/// Tokens left for the summary written by `--overflow summarize`.
+const SUMMARY_TOKENS: usize = 1024;
+
+/// The context window of `model` from the cached catalog, else from the
+/// built-in table; `None` when neither knows the model.
+fn known_context_window(cli: &Cli, model: &str) -> Option<usize> {
+    cli.catalog
+        .as_ref()
+        .and_then(|catalog| catalog.context_length(model))
+        .or_else(|| context::model_info(model).map(|info| info.context_window))
+}
+
+/// Shortens `contents` with the `--overflow` strategy when they do not fit in
+/// the context window of `model`, reporting each cut on stderr. Unknown models
+/// are only checked with `--context-window`.
+async fn fit_context(
+    cli: &Cli,
+    provider: &dyn Provider,
+    model: &str,
+    system: &[String],
+    contents: &mut [String],
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if cli.debug {
-        println!("Model: {}", cli.model);
-        println!("Markdown: {}", cli.markdown);
-        println!("Input content:");
-        for (i, content) in contents.iter().enumerate() {
-            println!("{}. {}", i + 1, content);
+    let family = Family::of(model);
+    let Some(window) = cli
+        .context_window
+        .or_else(|| known_context_window(cli, model))
+    else {
+        return Ok(());
+    };
+
+    let budget = context::input_budget(family, window, system);
+    let tokens = context::estimate_contents(family, contents);
+    debug!("Input tokens: ~{} of {} available", tokens, budget);
+    if tokens <= budget {
+        return Ok(());
+    }
+
+    let cuts = match cli.overflow {
+        Overflow::Error => {
+            return Err(context::ContextError::TooLong {
+                model: model.to_string(),
+                tokens,
+                limit: budget,
+            }
+            .into())
+        }
+        Overflow::Summarize => {
+            let budget = budget.saturating_sub(SUMMARY_TOKENS.min(budget / 8));
+            context::fit(contents, family, budget, cli.overflow)
+        }
+        overflow => context::fit(contents, family, budget, overflow),
+    };
+
+    let mut progress = progress(cli);
+    for cut in &cuts {
+        writeln!(
+            progress,
+            "Input {} is too long for {}: cut {} lines (~{} tokens) {}",
+            cut.index + 1,
+            model,
+            cut.lines,
+            cut.tokens,
+            match cli.overflow {
+                Overflow::TruncateMiddle => "from the middle",
+                Overflow::Summarize => "from the start and summarized them",
+                _ => "from the start",
+            }
+        )?;
+        if cli.overflow == Overflow::Summarize {
+            let summary = summarize_cut(cli, provider, cut).await?;
+            let content = &mut contents[cut.index];
+            let rest = content.split_once('\n').map_or("", |(_, rest)| rest);
+            *content = format!(
+                "[Summary of the first {} lines, cut to fit the context window]\n{}\n[End of summary]\n{}",
+                cut.lines,
+                summary.trim(),
+                rest
+            );
         }
     }
 
-    if contents.is_empty() {
-        if cli.debug {
-            println!("No input provided. Exiting.");
+    Ok(())
+}
+
+/// Summarizes the text removed by `cut` with the cheap model, shortening it
+/// first if it does not fit that model either.
+async fn summarize_cut(
+    cli: &Cli,
+    provider: &dyn Provider,
+    cut: &Cut,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let system = vec![prompts::SUMMARIZE_CUT.to_string()];
+    let mut contents = vec![cut.removed.clone()];
+    if let Some(window) = known_context_window(cli, &cli.cheap_model) {
+        let family = Family::of(&cli.cheap_model);
+        let budget = context::input_budget(family, window, &system);
+        context::fit(&mut contents, family, budget, Overflow::TruncateMiddle);
+    }
+
+    let request = ChatRequest {
+        model: cli.cheap_model.clone(),
+        system,
+        contents,
+        ..ChatRequest::default()
+    };
+    let completion =
+        complete_with_cancellation(provider, &request, OutputFormat::Text, &mut io::sink()).await?;
+    Ok(completion.content)
+}
+
+/// Copies the answer with `--copy`, after it was printed and recorded. A
+/// failure is only a warning, since the answer itself was delivered.
+pub fn copy_answer(cli: &Cli, report: &Report) {
+    if !cli.copy {
+        return;
+    }
+    if let Err(e) = copy_response(&report.content, cli.code_only) {
+        warn!("Could not copy the answer: {}", e);
+    }
+}
+
+/// Places the response, or with `code_only` just its code blocks, on the
+/// clipboard.
+fn copy_response(content: &str, code_only: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if code_only {
+        let blocks = markdown::code_blocks(content);
+        if blocks.is_empty() {
+            return Err("No code blocks in the response to copy".into());
+        }
+        clipboard::write(&blocks.join("\n\n"))?;
+    } else {
+        clipboard::write(content)?;
+    }
+    Ok(())
+}
+
+/// Asks for an answer matching `schema` and returns it pretty-printed as the
+/// completion's content. Invalid answers are sent back with the validation
+/// errors up to `retries` times. `response_format` is tried first; if the API
+/// rejects it, the schema is described in a system prompt instead.
+async fn complete_with_schema(
+    provider: &dyn Provider,
+    mut request: ChatRequest,
+    schema: &Schema,
+    retries: u32,
+) -> Result<Completion, Box<dyn std::error::Error>> {
+    request.response_format = Some(schema.response_format());
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        let result =
+            complete_with_cancellation(provider, &request, OutputFormat::Text, &mut io::sink())
+                .await;
+        let mut completion = match result {
+            Err(e)
+                if request.response_format.is_some()
+                    && matches!(e.downcast_ref(), Some(ApiError::ApiErrorResponse(_))) =>
+            {
+                info!(
+                    "response_format rejected ({}), using prompt instructions",
+                    e
+                );
+                request.response_format = None;
+                request.system.push(schema.instructions());
+                attempt -= 1;
+                continue;
+            }
+            result => result?,
+        };
+
+        match schema.validate(&completion.content) {
+            Ok(value) => {
+                completion.content = serde_json::to_string_pretty(&value)?;
+                return Ok(completion);
+            }
+            Err(errors) if attempt > retries => {
+                return Err(SchemaError::ValidationFailed(attempt, errors).into());
+            }
+            Err(errors) => {
+                info!("Attempt {} did not match the schema:", attempt);
+                for error in &errors {
+                    info!("  {}", error);
+                }
+                request
+                    .contents
+                    .push(schema.feedback(&completion.content, &errors));
+            }
         }
+    }
+}
+
+/// Logs the message list as it will be sent, at debug level. Image data is
+/// replaced by its size.
+fn log_messages(request: &ChatRequest) -> Result<(), serde_json::Error> {
+    if !tracing::enabled!(tracing::Level::DEBUG) {
+        return Ok(());
+    }
+    let mut messages = api::build_messages(&request.system, &request.history, &request.contents);
+    let images: Vec<Image> = request
+        .images
+        .iter()
+        .map(|image| Image {
+            data: format!("<{} base64 bytes>", image.data.len()),
+            ..image.clone()
+        })
+        .collect();
+    api::attach_images(&mut messages, &images);
+    debug!("Messages: {}", serde_json::to_string_pretty(&messages)?);
+    Ok(())
+}
+
+/// Runs the config pipeline `name` on `contents` for `mergil pipe`, writing
+/// the output of its last step to `out` and progress to stderr.
+pub async fn run_pipe(
+    cli: &Cli,
+    config: &Config,
+    name: &str,
+    contents: &[String],
+    provider: &dyn Provider,
+    out: &mut (dyn Write + Send),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(pipe) = config.pipelines.get(name) else {
+        let mut available: Vec<String> = config.pipelines.keys().cloned().collect();
+        available.sort();
+        return Err(PipelineError::NotFound(name.to_string(), available).into());
+    };
+    if pipe.steps.is_empty() {
+        return Err(PipelineError::NoSteps(name.to_string()).into());
+    }
+    if contents.is_empty() {
+        info!("No input provided. Exiting.");
         return Ok(());
     }
 
-    // Skip API call when running tests
-    if std::env::var("RUST_TEST").is_err() {
-        let api_key = api::get_api_key();
-        let client = reqwest::Client::new();
-
-        let mut input_contents = contents.to_vec();
-
-        if cli.preprocess {
-            let preprocessed_message = api::send_api_request(
-                &client,
-                &api_key,
-                &cli.model,
-                &input_contents,
-                cli.markdown,
-                None,
-                true,
-            )
-            .await?;
-            if cli.debug {
-                println!("Preprocessed message: {}", preprocessed_message);
+    let contents = if cli.no_redact {
+        contents.to_vec()
+    } else {
+        let (redacted, redactions) = Redactor::new(&config.redact.patterns)?.redact_all(contents);
+        for redaction in &redactions {
+            warn!("Redacted {}: {}", redaction.kind, redaction.count);
+        }
+        redacted
+    };
+    let model = model_chain(cli, config, None).remove(0);
+    let steps: Vec<StepConfig> = pipe
+        .steps
+        .iter()
+        .map(|step| StepConfig {
+            model: step.model.as_deref().map(|m| config.resolve_alias(m)),
+            ..step.clone()
+        })
+        .collect();
+
+    let mut progress = progress(cli);
+    let run = pipeline::run_steps(provider, &steps, &contents, &model, &mut *progress);
+    let output = interrupt::interruptible(run)
+        .await
+        .ok_or(ApiError::Cancelled)??;
+
+    display(cli).print(&output, cli.markdown, out)?;
+    Ok(())
+}
+
+/// How answers are printed, from `--color`, `--width` and `--no-pager`.
+fn display(cli: &Cli) -> Display {
+    let width = cli.width.map(usize::from);
+    Display::detect(cli.color, width, cli.no_pager, cli.stdout_tty)
+}
+
+/// The provider's model catalog from the cache, fetched again when it is
+/// older than a day, was fetched from another API, or `refresh` is set.
+pub async fn load_catalog(
+    cli: &Cli,
+    config: &Config,
+    cache: Option<&ModelCache>,
+    refresh: bool,
+) -> Result<Catalog, Box<dyn std::error::Error>> {
+    let source = base_url(cli, config);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    if let (Some(cache), false) = (cache, refresh) {
+        if let Some(catalog) = cache.load()? {
+            if catalog.is_fresh(&source, now) {
+                return Ok(catalog);
             }
-            // Replace the last message with the preprocessed message
-            if !input_contents.is_empty() {
-                *input_contents.last_mut().unwrap() = preprocessed_message;
-            } else {
-                input_contents.push(preprocessed_message);
+        }
+    }
+
+    let client = http_client(cli, config)?;
+    // Model lists are public on OpenRouter and Ollama, so a key is optional.
+    let api_key = api_key_source(cli, config).resolve().unwrap_or_default();
+    let catalog = Catalog {
+        models: models::fetch(&client, &source, &api_key, ListFormat::of(&source)).await?,
+        source,
+        fetched: now,
+    };
+    if let Some(cache) = cache {
+        cache.save(&catalog)?;
+    }
+    Ok(catalog)
+}
+
+/// Prints the model catalog for `mergil models`.
+pub fn list_models(
+    catalog: &Catalog,
+    search: Option<&str>,
+    ids: bool,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let found = match search {
+        Some(query) => catalog.search(query),
+        None => catalog.models.iter().collect(),
+    };
+
+    if ids {
+        for model in found {
+            writeln!(out, "{}", model.id)?;
+        }
+        return Ok(());
+    }
+
+    if found.is_empty() {
+        writeln!(out, "No models found at {}", catalog.source)?;
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "{:<48} {:>8} {:>10} {:>10}  MODALITY",
+        "MODEL", "CONTEXT", "INPUT/M", "OUTPUT/M"
+    )?;
+    for model in found {
+        writeln!(
+            out,
+            "{:<48} {:>8} {:>10} {:>10}  {}",
+            model.id,
+            model
+                .context_length
+                .map_or_else(|| "-".to_string(), models::format_tokens),
+            models::format_price(model.prompt_price),
+            models::format_price(model.completion_price),
+            model.modality.as_deref().unwrap_or("-")
+        )?;
+    }
+    Ok(())
+}
+
+/// The cached model catalog, if it was fetched from the API in use.
+pub fn cached_catalog(cli: &Cli, config: &Config) -> Option<Catalog> {
+    let catalog = ModelCache::from_env()?.load().ok().flatten()?;
+    (catalog.source == base_url(cli, config)).then_some(catalog)
+}
+
+/// Checks the first model of `--model` against the cached catalog, suggesting
+/// close matches for typos. Nothing is fetched; without a current cache every
+/// model is accepted, and an outdated one only warns, since the model may have
+/// been released after it was fetched.
+pub fn check_model(
+    cli: &Cli,
+    config: &Config,
+    cache: &ModelCache,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if cli.model.is_none() {
+        return Ok(());
+    }
+    let model = model_chain(cli, config, None).remove(0);
+    // The cached catalog lists only the models of the default API.
+    if config.route(&model).is_some() {
+        return Ok(());
+    }
+    let source = base_url(cli, config);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    match cache.load() {
+        Ok(Some(catalog)) if catalog.is_fresh(&source, now) => Ok(catalog.validate(&model)?),
+        Ok(Some(catalog)) if catalog.source == source => {
+            if let Err(e) = catalog.validate(&model) {
+                warn!("{}", e);
             }
+            Ok(())
         }
+        _ => Ok(()),
+    }
+}
 
-        let response = api::send_api_request(
-            &client,
-            &api_key,
-            &cli.model,
-            &input_contents,
-            cli.markdown,
-            None,
-            false,
-        )
-        .await?;
+/// Runs `mergil serve`: the OpenAI-compatible API on `host:port`, answered by
+/// `provider`. The bearer token is read from `MERGIL_SERVE_TOKEN`, or
+/// generated and printed to stderr.
+pub async fn run_server(
+    cli: &Cli,
+    config: &Config,
+    host: &str,
+    port: u16,
+    provider: Arc<dyn Provider>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = match std::env::var(server::TOKEN_ENV) {
+        Ok(token) if !token.is_empty() => token,
+        _ => {
+            let token = server::generate_token()?;
+            eprintln!("Bearer token: {}", token);
+            token
+        }
+    };
+    let default_model = model_chain(cli, config, None).remove(0);
+    let mut models = vec![default_model.clone()];
+    if cli.cheap_model != default_model {
+        models.push(cli.cheap_model.clone());
+    }
+    let mut aliases: Vec<String> = config.aliases.keys().cloned().collect();
+    aliases.sort();
+    models.extend(aliases);
+    let redactor = if cli.no_redact {
+        None
+    } else {
+        Some(Redactor::new(&config.redact.patterns)?)
+    };
 
-        let skin = markdown::create_madskin();
+    let router = server::router(
+        provider,
+        ServerOptions {
+            token,
+            default_model,
+            models,
+            aliases: config.aliases.clone(),
+            redactor,
+        },
+    );
+    let listener = tokio::net::TcpListener::bind((host, port)).await?;
+    info!("Listening on http://{}/v1", listener.local_addr()?);
+    server::serve(listener, router).await?;
+    Ok(())
+}
 
-        if cli.markdown {
-            skin.print_text(&response);
-        } else {
-            println!("{}", response);
+/// Prints the configured pipelines for `mergil pipe` without a name.
+pub fn list_pipelines(config: &Config, out: &mut dyn Write) -> io::Result<()> {
+    if config.pipelines.is_empty() {
+        writeln!(out, "No pipelines configured")?;
+    }
+    let mut names: Vec<&String> = config.pipelines.keys().collect();
+    names.sort();
+    for name in names {
+        let pipe = &config.pipelines[name];
+        match &pipe.description {
+            Some(description) => writeln!(out, "{:<20} {}", name, description)?,
+            None => writeln!(out, "{:<20} {} steps", name, pipe.steps.len())?,
+        }
+    }
+    Ok(())
+}
+
+/// Prints the installed templates for `mergil templates list`.
+pub fn list_templates(
+    config: &Config,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = template::templates_dir(config).ok_or("No templates directory found")?;
+    let templates = template::list(&dir)?;
+
+    if templates.is_empty() {
+        writeln!(out, "No templates found in {}", dir.display())?;
+    }
+    for template in templates {
+        writeln!(
+            out,
+            "{:<24} {}",
+            template.name,
+            template.description.unwrap_or_default()
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Adds a finished run to the history, unless `--private` is set. The prompt
+/// is redacted like the request was.
+pub fn record_history(
+    cli: &Cli,
+    config: &Config,
+    history: &History,
+    contents: &[String],
+    report: &Report,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if cli.private {
+        return Ok(());
+    }
+
+    let prompt = if cli.no_redact {
+        contents.to_vec()
+    } else {
+        Redactor::new(&config.redact.patterns)?
+            .redact_all(contents)
+            .0
+    };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    let template_model = cli
+        .template
+        .as_deref()
+        .and_then(|name| Template::load(&template::templates_dir(config)?, name).ok())
+        .and_then(|template| template.model);
+    let models = model_chain(cli, config, template_model.as_deref());
+
+    history.record(Entry {
+        id: 0,
+        time,
+        model: report.model.clone().unwrap_or_else(|| models[0].clone()),
+        cwd: std::env::current_dir().unwrap_or_default(),
+        session: report.session_id.clone(),
+        prompt,
+        answer: report.content.clone(),
+        options: RunOptions {
+            models,
+            template: cli.template.clone(),
+            vars: cli.vars.clone(),
+            system: cli.system.clone(),
+            persona: cli.persona.clone(),
+            no_system: cli.no_system,
+        },
+    })?;
+
+    Ok(())
+}
+
+/// The command line for `history rerun`: the options of `entry` on top of
+/// `cli`, with `model` replacing its models when given. Warns when the prompt
+/// had secrets redacted, since only the placeholders were kept.
+pub fn rerun_cli(cli: Cli, entry: &Entry, model: Option<String>) -> Cli {
+    if entry
+        .prompt
+        .iter()
+        .any(|content| content.contains("[REDACTED:"))
+    {
+        warn!(
+            "History entry {} had secrets redacted; the placeholders are sent instead",
+            entry.id
+        );
+    }
+    let options = entry.options.clone();
+    let models = match options.models.is_empty() {
+        true => entry.model.clone(),
+        false => options.models.join(","),
+    };
+    Cli {
+        model: Some(model.unwrap_or(models)),
+        template: options.template,
+        vars: options.vars,
+        system: options.system,
+        persona: options.persona,
+        no_system: options.no_system,
+        ..cli
+    }
+}
+
+/// Prints the history for `mergil history`, oldest first, keeping only entries
+/// whose prompt or answer matches `grep`.
+pub fn list_history(
+    history: &History,
+    grep: Option<&str>,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pattern = grep
+        .map(|pattern| RegexBuilder::new(pattern).case_insensitive(true).build())
+        .transpose()?;
+
+    let entries = history.entries()?;
+    if entries.is_empty() {
+        writeln!(out, "No history in {}", history.path().display())?;
+    }
+    for entry in entries {
+        let prompt = entry.prompt.join("\n");
+        if let Some(pattern) = &pattern {
+            if !pattern.is_match(&prompt) && !pattern.is_match(&entry.answer) {
+                continue;
+            }
         }
+
+        let first_line = prompt.lines().find(|line| !line.trim().is_empty());
+        let mut summary: String = first_line.unwrap_or_default().chars().take(60).collect();
+        if prompt.trim().chars().count() > summary.chars().count() {
+            summary.push_str("...");
+        }
+        writeln!(
+            out,
+            "{:>5}  {}  {:<32} {}",
+            entry.id,
+            history::format_time(entry.time),
+            entry.model,
+            summary
+        )?;
     }
 
     Ok(())
 }
+
+/// Prints one history entry with its answer for `mergil history show`.
+pub fn show_history(
+    history: &History,
+    id: u64,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entry = history.get(id)?;
+    writeln!(out, "Id:      {}", entry.id)?;
+    writeln!(out, "Time:    {} UTC", history::format_time(entry.time))?;
+    writeln!(out, "Model:   {}", entry.model)?;
+    writeln!(out, "Cwd:     {}", entry.cwd.display())?;
+    writeln!(out, "Session: {}", entry.session)?;
+    writeln!(
+        out,
+        "\n## Prompt\n\n{}",
+        entry.prompt.join("\n\n").trim_end()
+    )?;
+    writeln!(out, "\n## Answer\n\n{}", entry.answer.trim_end())?;
+    Ok(())
+}
+
+/// Runs a completion that Ctrl-C can cancel. With `OutputFormat::Ndjson`
+/// every delta is written to `out` as it arrives. Ctrl-C drops the in-flight
+/// request, writes whatever was streamed so far in text mode and returns
+/// `ApiError::Cancelled`; pressing it again, with nothing in flight, exits.
+pub(crate) async fn complete_with_cancellation(
+    provider: &dyn Provider,
+    request: &ChatRequest,
+    format: OutputFormat,
+    out: &mut (dyn Write + Send),
+) -> Result<Completion, Box<dyn std::error::Error>> {
+    let mut partial = String::new();
+    let mut write_result = Ok(());
+    let result = {
+        let mut on_delta = |delta: &str| {
+            partial.push_str(delta);
+            if format == OutputFormat::Ndjson && write_result.is_ok() {
+                write_result = serde_json::to_string(&Event::Delta { content: delta })
+                    .map_err(io::Error::from)
+                    .and_then(|line| writeln!(out, "{}", line));
+            }
+        };
+        interrupt::interruptible(provider.complete(request, &mut on_delta)).await
+    };
+    write_result?;
+
+    match result {
+        Some(result) => Ok(result?),
+        None => {
+            if format == OutputFormat::Text && !partial.is_empty() {
+                writeln!(out, "{}", partial)?;
+            }
+            Err(ApiError::Cancelled.into())
+        }
+    }
+}
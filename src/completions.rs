@@ -0,0 +1,93 @@
+use crate::config::Config;
+use crate::models::{Catalog, ModelCache};
+use crate::template;
+use clap::CommandFactory;
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::Shells;
+use clap_complete::Shell;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+
+/// Environment variable that makes `mergil` print completions instead of
+/// running, set by the registration scripts.
+pub const COMPLETE_ENV: &str = "COMPLETE";
+
+/// Writes the script that registers completions for `shell`. The script calls
+/// back into `mergil` on every completion, so models and templates are looked
+/// up when completing rather than when the script was generated.
+pub fn write_registration(shell: Shell, out: &mut dyn Write) -> io::Result<()> {
+    let name = shell.to_string();
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&name)
+        .ok_or_else(|| io::Error::other(format!("No completions for {}", name)))?;
+    completer.write_registration(COMPLETE_ENV, "mergil", "mergil", "mergil", out)
+}
+
+/// Writes the roff man page for `mergil`.
+pub fn write_man_page(out: &mut dyn Write) -> io::Result<()> {
+    clap_mangen::Man::new(crate::common::Cli::command()).render(out)
+}
+
+/// Config aliases first, sorted, then the ids of the cached catalog.
+pub fn model_names(config: &Config, catalog: Option<&Catalog>) -> Vec<String> {
+    let mut aliases: Vec<String> = config.aliases.keys().cloned().collect();
+    aliases.sort();
+    let ids = catalog
+        .into_iter()
+        .flat_map(|catalog| catalog.models.iter().map(|m| m.id.clone()));
+    aliases.into_iter().chain(ids).collect()
+}
+
+/// The names starting with the last model of `current`, a comma-separated
+/// fallback chain, each completed to the whole chain.
+pub fn complete_chain(current: &str, names: &[String]) -> Vec<String> {
+    let (done, last) = match current.rsplit_once(',') {
+        Some((done, last)) => (format!("{},", done), last),
+        None => (String::new(), current),
+    };
+    names
+        .iter()
+        .filter(|name| name.starts_with(last))
+        .map(|name| format!("{}{}", done, name))
+        .collect()
+}
+
+/// Completes `--model` from the config aliases and the cached model list.
+/// Nothing is fetched, so run `mergil models` once to fill the cache.
+pub fn complete_model(current: &OsStr) -> Vec<CompletionCandidate> {
+    let config = Config::load().unwrap_or_default();
+    let catalog = ModelCache::from_env().and_then(|cache| cache.load().ok().flatten());
+    let names = model_names(&config, catalog.as_ref());
+    complete_chain(&current.to_string_lossy(), &names)
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Completes `--template` with the installed templates and their descriptions.
+pub fn template_candidates() -> Vec<CompletionCandidate> {
+    let config = Config::load().unwrap_or_default();
+    let templates = template::templates_dir(&config)
+        .and_then(|dir| template::list(&dir).ok())
+        .unwrap_or_default();
+    templates
+        .into_iter()
+        .map(|t| CompletionCandidate::new(t.name).help(t.description.map(Into::into)))
+        .collect()
+}
+
+/// Completes `mergil pipe` with the pipelines from the config.
+pub fn pipeline_candidates() -> Vec<CompletionCandidate> {
+    let config = Config::load().unwrap_or_default();
+    let mut names: Vec<(String, Option<String>)> = config
+        .pipelines
+        .into_iter()
+        .map(|(name, pipeline)| (name, pipeline.description))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|(name, description)| CompletionCandidate::new(name).help(description.map(Into::into)))
+        .collect()
+}
@@ -0,0 +1,234 @@
+use crate::template::expand_home;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Settings read from `~/.config/mergil/config.toml`. Every field is optional;
+/// command-line flags take precedence over values set here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Model to use, or a list of models to fall back through in order
+    pub model: Option<ModelSpec>,
+    /// Short names for models, usable wherever a model is given
+    pub aliases: HashMap<String, String>,
+    /// Seconds to wait for the connection to the API to be established
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for a whole request, including the streamed response
+    pub timeout: Option<u64>,
+    /// Root of the OpenAI-compatible API, e.g. `https://openrouter.ai/api/v1`
+    pub base_url: Option<String>,
+    /// Environment variable holding the API key
+    pub api_key_env: Option<String>,
+    /// File containing the API key; a leading `~` is the home directory
+    pub api_key_file: Option<PathBuf>,
+    /// Shell command printing the API key, e.g. `pass show openrouter`
+    pub api_key_cmd: Option<String>,
+    /// Secret redaction applied to prompts before they are sent
+    pub redact: RedactConfig,
+    /// Directory holding prompt templates [default: ~/.config/mergil/templates]
+    pub templates_dir: Option<PathBuf>,
+    /// Overrides for the built-in system prompts
+    pub prompts: PromptsConfig,
+    /// Named system prompts selectable with `--persona`
+    pub personas: HashMap<String, String>,
+    /// The editor opened when no prompt is given
+    pub editor: EditorConfig,
+    /// Where past prompts are recorded
+    pub history: HistoryConfig,
+    /// Multi-step workflows run with `mergil pipe <name>`
+    pub pipelines: HashMap<String, PipelineConfig>,
+    /// Other OpenAI-compatible APIs, for models written `<name>:<model>`
+    pub providers: HashMap<String, ProviderConfig>,
+}
+
+/// An API that models written `<name>:<model>` are sent to, e.g. a local
+/// Ollama server as `local:qwen2.5`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderConfig {
+    /// Root of the API, e.g. `http://localhost:11434/v1`
+    pub base_url: String,
+    /// Environment variable holding the API key; none is sent when unset
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+}
+
+/// `model = "sonnet"` or `model = ["sonnet", "gpt-4o"]`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ModelSpec {
+    One(String),
+    Chain(Vec<String>),
+}
+
+impl ModelSpec {
+    pub fn models(&self) -> Vec<String> {
+        match self {
+            ModelSpec::One(model) => vec![model.clone()],
+            ModelSpec::Chain(models) => models.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Shown in the list of pipelines
+    pub description: Option<String>,
+    /// Steps in order; the output of the last one is printed
+    pub steps: Vec<StepConfig>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StepConfig {
+    /// Referenced by `after` of later steps [default: the step's number]
+    pub name: Option<String>,
+    /// Model answering this step [default: --model]
+    pub model: Option<String>,
+    /// System prompt of this step; none when unset
+    pub system: Option<String>,
+    /// What the step is sent
+    pub input: StepInput,
+    /// Steps whose output this one needs [default: the step before, unless
+    /// `input` is `original`]
+    pub after: Option<Vec<String>>,
+    /// What is kept of the answer
+    pub output: StepOutput,
+}
+
+/// The messages sent to a pipeline step.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepInput {
+    /// The pipeline's input
+    Original,
+    /// The outputs of the steps it runs after
+    #[default]
+    Previous,
+    /// The pipeline's input followed by the outputs of the steps it runs after
+    Both,
+}
+
+/// The part of a step's answer passed on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepOutput {
+    /// The whole answer
+    #[default]
+    Text,
+    /// Only the code blocks, joined by blank lines
+    Code,
+    /// The JSON value in the answer, pretty-printed
+    Json,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// History file [default: ~/.local/share/mergil/history.jsonl]
+    pub path: Option<PathBuf>,
+    /// How many entries to keep; older ones are dropped [default: 1000]
+    pub max_entries: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditorConfig {
+    /// Lines starting with this are dropped from the editor buffer [default: #];
+    /// an empty string keeps every line
+    pub comment_prefix: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactConfig {
+    /// Extra regexes to redact on top of the built-in secret patterns
+    pub patterns: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    ReadFailed(PathBuf, std::io::Error),
+    ParseFailed(PathBuf, toml::de::Error),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::ReadFailed(path, e) => {
+                write!(f, "Failed to read config {}: {}", path.display(), e)
+            }
+            ConfigError::ParseFailed(path, e) => {
+                write!(f, "Failed to parse config {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads the config file, returning the defaults when it does not exist.
+    pub fn load() -> Result<Config, ConfigError> {
+        match config_path() {
+            Some(path) if path.exists() => Config::from_path(path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// The configured provider named by the prefix of `model` and the model
+    /// without it, if `model` is written `<name>:<model>` for one.
+    pub fn route<'a>(&self, model: &'a str) -> Option<(&str, &'a str)> {
+        let (name, model) = model.split_once(':')?;
+        let (name, _) = self.providers.get_key_value(name)?;
+        Some((name.as_str(), model))
+    }
+
+    /// The model `name` stands for, or `name` itself when it is no alias.
+    pub fn resolve_alias(&self, name: &str) -> String {
+        self.aliases
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    pub fn from_path(path: PathBuf) -> Result<Config, ConfigError> {
+        let text =
+            fs::read_to_string(&path).map_err(|e| ConfigError::ReadFailed(path.clone(), e))?;
+        let mut config: Config =
+            toml::from_str(&text).map_err(|e| ConfigError::ParseFailed(path, e))?;
+        for path in [
+            &mut config.api_key_file,
+            &mut config.templates_dir,
+            &mut config.history.path,
+        ] {
+            *path = path.take().map(expand_home);
+        }
+        Ok(config)
+    }
+}
+
+/// `MERGIL_CONFIG` overrides the default location under the user config dir.
+pub fn config_path() -> Option<PathBuf> {
+    match env::var_os("MERGIL_CONFIG") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => dirs::config_dir().map(|dir| dir.join("mergil").join("config.toml")),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptsConfig {
+    /// Main system prompt sent with every request
+    pub system: Option<String>,
+    /// Formatting instructions used with `--markdown`
+    pub markdown: Option<String>,
+    /// Formatting instructions used without `--markdown`
+    pub plain: Option<String>,
+    /// Reformulation prompt used by `--preprocess`
+    pub preprocess: Option<String>,
+}
@@ -0,0 +1,384 @@
+use clap::ValueEnum;
+
+/// Tokens left for the answer when fitting the input into the context window,
+/// unless the window is small, in which case a quarter of it is kept free.
+const RESERVED_OUTPUT_TOKENS: usize = 4096;
+
+/// Tokens added per message for the role and separators.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Model ids with their family and context window in tokens, for when the
+/// cached catalog does not know them. Ids must match exactly: a newer model
+/// sharing a prefix, like `openai/gpt-4.1`, often has a larger window.
+const MODELS: &[(&str, Family, usize)] = &[
+    ("anthropic/claude-3-haiku", Family::Claude, 200_000),
+    ("anthropic/claude-3-opus", Family::Claude, 200_000),
+    ("anthropic/claude-3-sonnet", Family::Claude, 200_000),
+    ("anthropic/claude-3.5-haiku", Family::Claude, 200_000),
+    ("anthropic/claude-3.5-sonnet", Family::Claude, 200_000),
+    ("anthropic/claude-3.7-sonnet", Family::Claude, 200_000),
+    ("openai/gpt-4o", Family::Gpt, 128_000),
+    ("openai/gpt-4o-mini", Family::Gpt, 128_000),
+    ("openai/gpt-4-turbo", Family::Gpt, 128_000),
+    ("openai/gpt-4", Family::Gpt, 8_192),
+    ("openai/gpt-3.5-turbo", Family::Gpt, 16_385),
+    ("openai/o1", Family::Gpt, 128_000),
+    ("openai/o1-mini", Family::Gpt, 128_000),
+    ("meta-llama/llama-3.1-405b", Family::Llama, 131_072),
+    ("meta-llama/llama-3.1-405b-instruct", Family::Llama, 131_072),
+    ("meta-llama/llama-3.1-70b-instruct", Family::Llama, 131_072),
+    ("meta-llama/llama-3.1-8b-instruct", Family::Llama, 131_072),
+    ("meta-llama/llama-3.2-1b-instruct", Family::Llama, 131_072),
+    ("meta-llama/llama-3.2-3b-instruct", Family::Llama, 131_072),
+    ("meta-llama/llama-3-70b-instruct", Family::Llama, 8_192),
+    ("meta-llama/llama-3-8b-instruct", Family::Llama, 8_192),
+    ("google/gemini-pro-1.5", Family::Gemini, 2_000_000),
+    ("google/gemini-flash-1.5", Family::Gemini, 1_000_000),
+    ("mistralai/mistral-large", Family::Mistral, 128_000),
+    ("mistralai/mistral-7b-instruct", Family::Mistral, 32_768),
+    ("mistralai/mixtral-8x7b-instruct", Family::Mistral, 32_768),
+    ("deepseek/deepseek-chat", Family::Other, 64_000),
+];
+
+/// Groups of models sharing a tokenizer, for estimating token counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Claude,
+    Gpt,
+    Llama,
+    Gemini,
+    Mistral,
+    Other,
+}
+
+impl Family {
+    /// Guesses the family from the model name when it is not in the table.
+    pub fn of(model: &str) -> Family {
+        if let Some(info) = model_info(model) {
+            return info.family;
+        }
+        let model = model.to_lowercase();
+        [
+            ("claude", Family::Claude),
+            ("gpt", Family::Gpt),
+            ("llama", Family::Llama),
+            ("gemini", Family::Gemini),
+            ("mistral", Family::Mistral),
+        ]
+        .into_iter()
+        .find(|(name, _)| model.contains(name))
+        .map_or(Family::Other, |(_, family)| family)
+    }
+
+    /// Average characters of English text or code per token.
+    fn chars_per_token(self) -> f64 {
+        match self {
+            Family::Gpt | Family::Llama | Family::Gemini => 4.0,
+            Family::Claude | Family::Mistral | Family::Other => 3.5,
+        }
+    }
+
+    /// Estimates how many tokens `text` takes. Non-ASCII characters are counted
+    /// as a token each, which overestimates slightly for most scripts.
+    pub fn estimate_tokens(self, text: &str) -> usize {
+        let ascii = text.bytes().filter(u8::is_ascii).count();
+        let other = text.chars().filter(|c| !c.is_ascii()).count();
+        (ascii as f64 / self.chars_per_token()).ceil() as usize + other
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelInfo {
+    pub family: Family,
+    pub context_window: usize,
+}
+
+/// Looks `model` up in the metadata table by its exact id. An `:online`-style
+/// suffix is ignored.
+pub fn model_info(model: &str) -> Option<ModelInfo> {
+    let model = model.split(':').next().unwrap_or(model);
+    MODELS
+        .iter()
+        .find(|(id, _, _)| *id == model)
+        .map(|&(_, family, context_window)| ModelInfo {
+            family,
+            context_window,
+        })
+}
+
+/// What to do when the input does not fit in the model's context window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    /// Drop lines from the start of the input
+    TruncateHead,
+    /// Drop lines from the middle of the input, keeping its start and end
+    TruncateMiddle,
+    /// Replace the start of the input with a summary made by the cheap model
+    Summarize,
+    /// Fail without sending the request
+    #[default]
+    Error,
+}
+
+/// Lines removed from one content entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cut {
+    /// Index of the entry in the contents
+    pub index: usize,
+    pub lines: usize,
+    pub tokens: usize,
+    /// The removed text, for summarizing
+    pub removed: String,
+}
+
+#[derive(Debug)]
+pub enum ContextError {
+    TooLong {
+        model: String,
+        tokens: usize,
+        limit: usize,
+    },
+}
+
+impl std::fmt::Display for ContextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContextError::TooLong {
+                model,
+                tokens,
+                limit,
+            } => write!(
+                f,
+                "Input is about {} tokens but {} accepts about {}; \
+                 use --overflow truncate-head, truncate-middle or summarize to shorten it",
+                tokens, model, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ContextError {}
+
+/// Tokens available for user contents in a `context_window`, after the
+/// system prompts and room for the answer.
+pub fn input_budget(family: Family, context_window: usize, system: &[String]) -> usize {
+    let reserved = RESERVED_OUTPUT_TOKENS.min(context_window / 4);
+    let system: usize = system.iter().map(|s| estimate_message(family, s)).sum();
+    context_window.saturating_sub(reserved + system)
+}
+
+/// Estimated tokens of all `contents`, each sent as its own message.
+pub fn estimate_contents(family: Family, contents: &[String]) -> usize {
+    contents.iter().map(|c| estimate_message(family, c)).sum()
+}
+
+fn estimate_message(family: Family, text: &str) -> usize {
+    family.estimate_tokens(text) + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Shortens `contents` until they fit in `budget` tokens, taking whole lines
+/// from the largest entries first, or part of a line when a single one does
+/// not fit. `Overflow::Summarize` cuts like `TruncateHead`; summarizing the cut
+/// text is up to the caller.
+pub fn fit(contents: &mut [String], family: Family, budget: usize, overflow: Overflow) -> Vec<Cut> {
+    let mut cuts = Vec::new();
+    let mut order: Vec<usize> = (0..contents.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(contents[i].len()));
+
+    for index in order {
+        let total = estimate_contents(family, contents);
+        if total <= budget {
+            break;
+        }
+        let over = total - budget;
+        let target = family
+            .estimate_tokens(&contents[index])
+            .saturating_sub(over);
+
+        let (kept, cut) = match overflow {
+            Overflow::TruncateMiddle => cut_middle(&contents[index], family, target),
+            _ => cut_head(&contents[index], family, target),
+        };
+        if cut.lines > 0 {
+            contents[index] = kept;
+            cuts.push(Cut { index, ..cut });
+        }
+    }
+
+    cuts
+}
+
+/// Keeps the last lines of `text` fitting in `target` tokens, marker included.
+/// When not even the last line fits, its end is kept.
+fn cut_head(text: &str, family: Family, target: usize) -> (String, Cut) {
+    let budget = target.saturating_sub(MARKER_TOKENS);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut used = 0;
+    let mut keep = 0;
+    for line in lines.iter().rev() {
+        let tokens = family.estimate_tokens(line) + 1;
+        if used + tokens > budget {
+            break;
+        }
+        used += tokens;
+        keep += 1;
+    }
+
+    if keep == 0 && !lines.is_empty() {
+        let start = tail_start(text, family, budget);
+        let removed = &text[..start];
+        let lines = removed.lines().count();
+        let kept = format!("{}\n{}", marker(lines), &text[start..]);
+        return (kept, cut(lines, family, removed.to_string()));
+    }
+
+    let split = lines.len() - keep;
+    let removed = lines[..split].join("\n");
+    let kept = format!("{}\n{}", marker(split), lines[split..].join("\n"));
+    (kept, cut(split, family, removed))
+}
+
+/// Keeps the first and last lines of `text`, half of `target` each. When no
+/// whole line fits on either side, the start and end of the text are kept.
+fn cut_middle(text: &str, family: Family, target: usize) -> (String, Cut) {
+    let lines: Vec<&str> = text.lines().collect();
+    let half = target.saturating_sub(MARKER_TOKENS) / 2;
+
+    let take = |iter: &mut dyn Iterator<Item = &&str>| {
+        let mut used = 0;
+        iter.take_while(|line| {
+            used += family.estimate_tokens(line) + 1;
+            used <= half
+        })
+        .count()
+    };
+    let head = take(&mut lines.iter());
+    let tail = take(&mut lines.iter().rev()).min(lines.len() - head);
+
+    if head == 0 && tail == 0 && !lines.is_empty() {
+        let end = head_end(text, family, half);
+        let start = tail_start(text, family, half).max(end);
+        let removed = &text[end..start];
+        let lines = removed.lines().count();
+        let kept = format!("{}\n{}\n{}", &text[..end], marker(lines), &text[start..]);
+        return (kept, cut(lines, family, removed.to_string()));
+    }
+
+    let cut_lines = lines.len() - head - tail;
+    let removed = lines[head..head + cut_lines].join("\n");
+    let kept = format!(
+        "{}\n{}\n{}",
+        lines[..head].join("\n"),
+        marker(cut_lines),
+        lines[head + cut_lines..].join("\n")
+    );
+    (kept, cut(cut_lines, family, removed))
+}
+
+/// Byte index where the longest start of `text` fitting in `tokens` ends,
+/// always at a character boundary.
+fn head_end(text: &str, family: Family, tokens: usize) -> usize {
+    let mut count = TokenCount::new(family);
+    text.char_indices()
+        .find(|&(_, c)| !count.add(c, tokens))
+        .map_or(text.len(), |(i, _)| i)
+}
+
+/// Byte index where the longest end of `text` fitting in `tokens` starts,
+/// always at a character boundary.
+fn tail_start(text: &str, family: Family, tokens: usize) -> usize {
+    let mut count = TokenCount::new(family);
+    let mut start = text.len();
+    for (i, c) in text.char_indices().rev() {
+        if !count.add(c, tokens) {
+            break;
+        }
+        start = i;
+    }
+    start
+}
+
+/// Counts tokens character by character, as `Family::estimate_tokens` does.
+struct TokenCount {
+    family: Family,
+    ascii: usize,
+    other: usize,
+}
+
+impl TokenCount {
+    fn new(family: Family) -> Self {
+        TokenCount {
+            family,
+            ascii: 0,
+            other: 0,
+        }
+    }
+
+    /// Adds `c` if the count stays within `limit`.
+    fn add(&mut self, c: char, limit: usize) -> bool {
+        let (ascii, other) = if c.is_ascii() {
+            (self.ascii + 1, self.other)
+        } else {
+            (self.ascii, self.other + 1)
+        };
+        let tokens = (ascii as f64 / self.family.chars_per_token()).ceil() as usize + other;
+        if tokens > limit {
+            return false;
+        }
+        self.ascii = ascii;
+        self.other = other;
+        true
+    }
+}
+
+const MARKER_TOKENS: usize = 12;
+
+fn marker(lines: usize) -> String {
+    format!("[... {} lines cut to fit the context window ...]", lines)
+}
+
+fn cut(lines: usize, family: Family, removed: String) -> Cut {
+    Cut {
+        index: 0,
+        lines,
+        tokens: family.estimate_tokens(&removed),
+        removed,
+    }
+}
+
+/// Splits `text` into chunks of at most `max_tokens`, breaking between
+/// paragraphs where possible and otherwise between lines. A single line longer
+/// than `max_tokens` becomes a chunk of its own.
+pub fn chunk(text: &str, family: Family, max_tokens: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+
+    for paragraph in text.split("\n\n") {
+        let paragraph_tokens = family.estimate_tokens(paragraph) + 1;
+        let pieces: Vec<&str> = if paragraph_tokens > max_tokens {
+            paragraph.lines().collect()
+        } else {
+            vec![paragraph]
+        };
+        let separator = if pieces.len() > 1 { "\n" } else { "\n\n" };
+
+        for piece in pieces {
+            let tokens = family.estimate_tokens(piece) + 1;
+            if current_tokens + tokens > max_tokens && !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+                current_tokens = 0;
+            }
+            if !current.is_empty() {
+                current.push_str(separator);
+            }
+            current.push_str(piece);
+            current_tokens += tokens;
+        }
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
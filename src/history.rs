@@ -0,0 +1,194 @@
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// How many entries are kept when the config does not say otherwise.
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// One recorded run: the prompt as sent and the answer it got.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub model: String,
+    pub cwd: PathBuf,
+    pub session: String,
+    pub prompt: Vec<String>,
+    pub answer: String,
+    /// Missing from entries recorded before options were kept
+    #[serde(default)]
+    pub options: RunOptions,
+}
+
+/// The options of a run that shape the request, replayed by `history rerun`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunOptions {
+    /// The models to try in order, aliases resolved
+    pub models: Vec<String>,
+    pub template: Option<String>,
+    pub vars: Vec<(String, String)>,
+    pub system: Option<String>,
+    pub persona: Option<String>,
+    pub no_system: bool,
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    ReadFailed(PathBuf, io::Error),
+    WriteFailed(PathBuf, io::Error),
+    InvalidEntry(PathBuf, usize, serde_json::Error),
+    NotFound(u64),
+}
+
+impl std::fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryError::ReadFailed(path, e) => {
+                write!(f, "Failed to read history {}: {}", path.display(), e)
+            }
+            HistoryError::WriteFailed(path, e) => {
+                write!(f, "Failed to write history {}: {}", path.display(), e)
+            }
+            HistoryError::InvalidEntry(path, line, e) => {
+                write!(
+                    f,
+                    "Invalid history entry {}:{}: {}",
+                    path.display(),
+                    line,
+                    e
+                )
+            }
+            HistoryError::NotFound(id) => write!(f, "No history entry with id {}", id),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+/// The prompt history, stored as one JSON entry per line.
+pub struct History {
+    path: PathBuf,
+    max_entries: usize,
+}
+
+impl History {
+    pub fn new(path: PathBuf, max_entries: usize) -> Self {
+        History { path, max_entries }
+    }
+
+    /// The history configured in `[history]`, by default
+    /// `~/.local/share/mergil/history.jsonl`.
+    pub fn from_config(config: &Config) -> Option<History> {
+        let path = config
+            .history
+            .path
+            .clone()
+            .or_else(|| dirs::data_dir().map(|dir| dir.join("mergil").join("history.jsonl")))?;
+        Some(History::new(
+            path,
+            config.history.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES),
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every entry, oldest first. A missing file is an empty history.
+    pub fn entries(&self) -> Result<Vec<Entry>, HistoryError> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(HistoryError::ReadFailed(self.path.clone(), e)),
+        };
+
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| HistoryError::ReadFailed(self.path.clone(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .map_err(|e| HistoryError::InvalidEntry(self.path.clone(), i + 1, e))?;
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    /// The most recently recorded entry, if there is one.
+    pub fn latest(&self) -> Result<Option<Entry>, HistoryError> {
+        Ok(self.entries()?.pop())
+    }
+
+    pub fn get(&self, id: u64) -> Result<Entry, HistoryError> {
+        self.entries()?
+            .into_iter()
+            .find(|entry| entry.id == id)
+            .ok_or(HistoryError::NotFound(id))
+    }
+
+    /// Appends `entry` with the next free id, which is returned. Once there are
+    /// more than `max_entries`, the oldest ones are dropped.
+    pub fn record(&self, mut entry: Entry) -> Result<u64, HistoryError> {
+        let write_failed = |e| HistoryError::WriteFailed(self.path.clone(), e);
+
+        let mut entries = self.entries()?;
+        entry.id = entries.last().map_or(1, |last| last.id + 1);
+        let id = entry.id;
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(write_failed)?;
+        }
+
+        if entries.len() < self.max_entries {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .map_err(write_failed)?;
+            writeln!(file, "{}", to_line(&entry)).map_err(write_failed)?;
+        } else {
+            entries.push(entry);
+            let keep = entries.split_off(entries.len() - self.max_entries);
+            let text: String = keep.iter().map(|e| to_line(e) + "\n").collect();
+            fs::write(&self.path, text).map_err(write_failed)?;
+        }
+
+        Ok(id)
+    }
+}
+
+fn to_line(entry: &Entry) -> String {
+    serde_json::to_string(entry).expect("history entries always serialize")
+}
+
+/// Formats Unix seconds as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let minutes = (secs % 86_400) / 60;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
@@ -0,0 +1,111 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The largest image accepted by `--image`, matching OpenAI's upload limit.
+pub const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// File signatures of the formats vision models accept, as `(magic, mime)`.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+];
+
+/// An image attached with `--image` or sent to `mergil serve`, base64-encoded
+/// for a `data:` URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub path: PathBuf,
+    pub mime: String,
+    pub data: String,
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    ReadFailed(PathBuf, io::Error),
+    TooLarge(PathBuf, u64),
+    UnsupportedFormat(PathBuf),
+    UnsupportedProvider,
+}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::ReadFailed(path, e) => {
+                write!(f, "Failed to read {}: {}", path.display(), e)
+            }
+            ImageError::TooLarge(path, size) => write!(
+                f,
+                "Image {} is {} bytes, the limit is {} bytes",
+                path.display(),
+                size,
+                MAX_IMAGE_BYTES
+            ),
+            ImageError::UnsupportedFormat(path) => write!(
+                f,
+                "Image {} is not a PNG, JPEG, GIF or WebP file",
+                path.display()
+            ),
+            ImageError::UnsupportedProvider => {
+                write!(
+                    f,
+                    "The selected provider or model does not support image input"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl Image {
+    /// Reads and encodes the image at `path`. The size is checked before the
+    /// file is read, and the type comes from its contents, not its extension.
+    pub fn load(path: &Path) -> Result<Image, ImageError> {
+        let size = fs::metadata(path)
+            .map_err(|e| ImageError::ReadFailed(path.into(), e))?
+            .len();
+        if size > MAX_IMAGE_BYTES {
+            return Err(ImageError::TooLarge(path.into(), size));
+        }
+
+        let bytes = fs::read(path).map_err(|e| ImageError::ReadFailed(path.into(), e))?;
+        let mime = sniff_mime(&bytes).ok_or_else(|| ImageError::UnsupportedFormat(path.into()))?;
+
+        Ok(Image {
+            path: path.to_path_buf(),
+            mime: mime.to_string(),
+            data: STANDARD.encode(&bytes),
+        })
+    }
+
+    /// The image of a base64 `data:` URL, as sent by API clients. It has no
+    /// path.
+    pub fn from_data_url(url: &str) -> Option<Image> {
+        let (mime, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+        Some(Image {
+            path: PathBuf::new(),
+            mime: mime.to_string(),
+            data: data.to_string(),
+        })
+    }
+
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime, self.data)
+    }
+}
+
+/// Detects the image type from its leading bytes.
+pub fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map(|(_, mime)| *mime)
+}
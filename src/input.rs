@@ -1,6 +1,28 @@
 use atty::Stream;
 use std::io::{self, Read};
-use tempfile::NamedTempFile;
+use std::path::PathBuf;
+
+/// Lines starting with this are dropped from the editor buffer, like in `git commit`.
+pub const DEFAULT_COMMENT_PREFIX: &str = "#";
+
+/// How many lines of piped stdin or of the last reply the header shows.
+const PREVIEW_LINES: usize = 10;
+
+/// GUI editors that return immediately unless told to wait for the file to be
+/// closed, as `(program, flag)`.
+const WAIT_FLAGS: &[(&str, &str)] = &[
+    ("code", "--wait"),
+    ("code-insiders", "--wait"),
+    ("codium", "--wait"),
+    ("cursor", "--wait"),
+    ("zed", "--wait"),
+    ("atom", "--wait"),
+    ("subl", "-w"),
+    ("mate", "-w"),
+    ("gvim", "-f"),
+    ("mvim", "-f"),
+    ("kate", "-b"),
+];
 
 pub enum InputResult {
     Content(String),
@@ -12,8 +34,16 @@ pub trait StdinReader {
     fn is_atty(&self) -> bool;
 }
 
+/// How the editor exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorStatus {
+    Saved,
+    /// The editor exited with an error, e.g. `:cq` in vim
+    Aborted,
+}
+
 pub trait EditorOpener {
-    fn open_editor(&self, temp_path: &str) -> io::Result<()>;
+    fn open_editor(&self, temp_path: &str) -> io::Result<EditorStatus>;
 }
 
 pub struct RealStdin;
@@ -31,22 +61,156 @@ impl StdinReader for RealStdin {
 pub struct RealEditor;
 
 impl EditorOpener for RealEditor {
-    fn open_editor(&self, temp_path: &str) -> io::Result<()> {
+    fn open_editor(&self, temp_path: &str) -> io::Result<EditorStatus> {
         use std::env;
         use std::process::Command;
 
-        let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
-        Command::new(&editor).arg(temp_path).status().map(|_| ())
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        let args = editor_command(&editor)?;
+        let status = Command::new(&args[0])
+            .args(&args[1..])
+            .arg(temp_path)
+            .status()
+            .map_err(|e| io::Error::new(e.kind(), format!("could not run `{}`: {}", editor, e)))?;
+
+        Ok(if status.success() {
+            EditorStatus::Saved
+        } else {
+            EditorStatus::Aborted
+        })
+    }
+}
+
+/// Splits an `$EDITOR` value like `emacsclient -t` or `"/opt/My Editor/subl"`
+/// into program and arguments, adding the wait flag GUI editors need.
+pub fn editor_command(editor: &str) -> io::Result<Vec<String>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+    let mut args = shell_words::split(editor)
+        .map_err(|e| invalid(format!("could not parse editor `{}`: {}", editor, e)))?;
+    let program = args
+        .first()
+        .ok_or_else(|| invalid("the editor command is empty".to_string()))?;
+
+    let name = std::path::Path::new(program)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if let Some((_, flag)) = WAIT_FLAGS.iter().find(|(editor, _)| *editor == name) {
+        if !args.iter().any(|arg| arg == flag) {
+            args.push(flag.to_string());
+        }
     }
+
+    Ok(args)
+}
+
+/// What the editor buffer is prefilled with: a commented header describing
+/// the request about to be sent.
+#[derive(Debug, Clone, Default)]
+pub struct EditorTemplate {
+    pub model: Option<String>,
+    pub files: Vec<PathBuf>,
+    pub stdin: Option<String>,
+    pub last_reply: Option<String>,
+    pub comment_prefix: Option<String>,
+}
+
+impl EditorTemplate {
+    fn prefix(&self) -> &str {
+        self.comment_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_COMMENT_PREFIX)
+    }
+
+    /// The initial buffer: an empty line to type on, then the commented
+    /// header. Empty when there is nothing to show or comments are disabled
+    /// with an empty prefix.
+    pub fn header(&self) -> String {
+        let mut lines = Vec::new();
+        if self.prefix().is_empty() {
+            return String::new();
+        }
+        if let Some(model) = &self.model {
+            lines.push(format!("Model: {}", model));
+        }
+        for file in &self.files {
+            lines.push(format!("File: {}", file.display()));
+        }
+        if let Some(stdin) = self.stdin.as_deref().filter(|s| !s.trim().is_empty()) {
+            lines.push("Piped input:".to_string());
+            lines.extend(preview(stdin));
+        }
+        if let Some(reply) = self.last_reply.as_deref().filter(|s| !s.trim().is_empty()) {
+            lines.push("Last reply:".to_string());
+            lines.extend(preview(reply));
+        }
+        if lines.is_empty() {
+            return String::new();
+        }
+
+        let prefix = self.prefix();
+        let mut header = format!(
+            "\n{p} Write your prompt above. Lines starting with '{p}' are ignored,\n\
+             {p} and an empty prompt cancels the request.\n{p}\n",
+            p = prefix
+        );
+        for line in lines {
+            header.push_str(&format!("{} {}\n", prefix, line));
+        }
+        header
+    }
+
+    /// Drops comment lines and surrounding blank lines from the saved buffer.
+    pub fn strip(&self, text: &str) -> String {
+        let prefix = self.prefix();
+        text.lines()
+            .filter(|line| prefix.is_empty() || !line.starts_with(prefix))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+}
+
+/// The first lines of `text`, indented, with a marker when some were cut.
+fn preview(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = text
+        .trim_end()
+        .lines()
+        .take(PREVIEW_LINES)
+        .map(|line| format!("  {}", line))
+        .collect();
+    if text.trim_end().lines().count() > PREVIEW_LINES {
+        lines.push("  ...".to_string());
+    }
+    lines
 }
 
 pub fn get_input(
     force_editor: bool,
     stdin_reader: &mut dyn StdinReader,
     editor_opener: &dyn EditorOpener,
+) -> io::Result<InputResult> {
+    get_input_with_template(
+        force_editor,
+        stdin_reader,
+        editor_opener,
+        &EditorTemplate::default(),
+    )
+}
+
+/// Like `get_input`, prefilling the editor buffer from `template`.
+pub fn get_input_with_template(
+    force_editor: bool,
+    stdin_reader: &mut dyn StdinReader,
+    editor_opener: &dyn EditorOpener,
+    template: &EditorTemplate,
 ) -> io::Result<InputResult> {
     if force_editor || stdin_reader.is_atty() {
-        open_editor(editor_opener)
+        open_editor(editor_opener, template)
     } else {
         let mut buffer = String::new();
         stdin_reader.read_to_string(&mut buffer)?;
@@ -58,17 +222,26 @@ pub fn get_input(
     }
 }
 
-fn open_editor(editor_opener: &dyn EditorOpener) -> io::Result<InputResult> {
-    use std::fs::{self, File};
+fn open_editor(
+    editor_opener: &dyn EditorOpener,
+    template: &EditorTemplate,
+) -> io::Result<InputResult> {
+    use std::fs;
 
-    let temp_file = NamedTempFile::new()?;
+    // The .md suffix gives editors Markdown syntax highlighting.
+    let temp_file = tempfile::Builder::new()
+        .prefix("mergil-")
+        .suffix(".md")
+        .tempfile()?;
     let temp_path = temp_file.path().to_str().unwrap().to_string();
 
-    File::create(&temp_path)?;
+    fs::write(&temp_path, template.header())?;
 
-    editor_opener.open_editor(&temp_path)?;
+    if editor_opener.open_editor(&temp_path)? == EditorStatus::Aborted {
+        return Ok(InputResult::Cancelled);
+    }
 
-    let contents = fs::read_to_string(&temp_path)?;
+    let contents = template.strip(&fs::read_to_string(&temp_path)?);
 
     if contents.trim().is_empty() {
         Ok(InputResult::Cancelled)
@@ -0,0 +1,100 @@
+use crate::output;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tokio::sync::watch;
+
+/// The handler installed by `install`, shared by every request.
+static INTERRUPTS: OnceLock<Interrupts> = OnceLock::new();
+
+/// What a Ctrl-C press does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Press {
+    /// Requests were in flight and are cancelled
+    Cancel,
+    /// Nothing was in flight, so the process should exit
+    Exit,
+}
+
+/// Ctrl-C presses, delivered to the requests in flight.
+pub struct Interrupts {
+    in_flight: AtomicUsize,
+    presses: watch::Sender<u64>,
+}
+
+impl Default for Interrupts {
+    fn default() -> Self {
+        Interrupts {
+            in_flight: AtomicUsize::new(0),
+            presses: watch::Sender::new(0),
+        }
+    }
+}
+
+impl Interrupts {
+    /// Cancels the requests in flight, if there are any.
+    pub fn press(&self) -> Press {
+        if self.in_flight.load(Ordering::SeqCst) == 0 {
+            return Press::Exit;
+        }
+        self.presses.send_modify(|presses| *presses += 1);
+        Press::Cancel
+    }
+
+    /// Runs `future` to completion, or returns `None` when Ctrl-C is pressed
+    /// first.
+    pub async fn interruptible<F: Future>(&self, future: F) -> Option<F::Output> {
+        let mut presses = self.presses.subscribe();
+        let _in_flight = InFlight::enter(&self.in_flight);
+        tokio::select! {
+            output = future => Some(output),
+            _ = presses.changed() => None,
+        }
+    }
+}
+
+/// Counts a request as in flight until dropped.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn enter(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        InFlight(count)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Installs the Ctrl-C handler for the whole process. A press cancels the
+/// requests in flight; with none, for example while waiting for the editor or
+/// a prompt, it exits with `EXIT_CANCELLED`. Must be called inside the runtime.
+pub fn install() {
+    let mut installed = false;
+    let interrupts = INTERRUPTS.get_or_init(|| {
+        installed = true;
+        Interrupts::default()
+    });
+    if !installed {
+        return;
+    }
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if interrupts.press() == Press::Exit {
+                std::process::exit(output::EXIT_CANCELLED);
+            }
+        }
+    });
+}
+
+/// Runs `future` until it completes or Ctrl-C cancels it, returning `None`
+/// then. Without `install`, as in tests, it always runs to completion.
+pub async fn interruptible<F: Future>(future: F) -> Option<F::Output> {
+    match INTERRUPTS.get() {
+        Some(interrupts) => interrupts.interruptible(future).await,
+        None => Some(future.await),
+    }
+}
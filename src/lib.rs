@@ -1,11 +1,174 @@
-use common::{handle_input, process_contents, Cli};
+use common::{
+    build_provider, cached_catalog, check_finished, check_model, copy_answer, handle_input,
+    list_history, list_models, list_pipelines, list_templates, load_catalog, model_chain,
+    process_contents, record_history, rerun_cli, resolve_aliases, run_pipe, run_server,
+    show_history, Cli, Command, HistoryCommand, TemplatesCommand,
+};
+use config::Config;
+use history::History;
+use models::ModelCache;
+use provider::{FallbackProvider, Provider};
+use std::io::{self, Write};
+use std::sync::Arc;
 
 pub mod api;
+pub mod chunked;
+pub mod clipboard;
 pub mod common;
+pub mod completions;
+pub mod config;
+pub mod context;
+pub mod history;
+pub mod image;
 pub mod input;
+pub mod interrupt;
+pub mod logging;
 pub mod markdown;
+pub mod models;
+pub mod output;
+pub mod pipeline;
+pub mod prompts;
+pub mod provider;
+pub mod redact;
+pub mod schema;
+pub mod server;
+pub mod shell;
+pub mod template;
+pub mod terminal;
 
 pub async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
-    let contents = handle_input(&cli).await?;
-    process_contents(&cli, &contents).await
+    let config = Config::load()?;
+    let history = History::from_config(&config);
+    let stored_history = || history.as_ref().ok_or("No data directory for the history");
+    // A rerun replays the recorded options, so they are in place before the
+    // model chain is built.
+    let (cli, rerun_prompt) = match &cli.command {
+        Some(Command::History {
+            command: Some(HistoryCommand::Rerun { id, model }),
+            ..
+        }) => {
+            let entry = stored_history()?.get(*id)?;
+            let model = model.clone();
+            (rerun_cli(cli, &entry, model), Some(entry.prompt))
+        }
+        _ => (cli, None),
+    };
+    let cli = resolve_aliases(cli, &config);
+    let cli = Cli {
+        stdout_tty: atty::is(atty::Stream::Stdout),
+        catalog: cached_catalog(&cli, &config),
+        ..cli
+    };
+    match &cli.command {
+        Some(Command::Templates {
+            command: TemplatesCommand::List,
+        }) => return list_templates(&config, &mut io::stdout()),
+        Some(Command::History {
+            grep,
+            command: None,
+        }) => return list_history(stored_history()?, grep.as_deref(), &mut io::stdout()),
+        Some(Command::History {
+            command: Some(HistoryCommand::Show { id }),
+            ..
+        }) => return show_history(stored_history()?, *id, &mut io::stdout()),
+        Some(Command::Models {
+            search,
+            refresh,
+            ids,
+        }) => {
+            let catalog =
+                load_catalog(&cli, &config, ModelCache::from_env().as_ref(), *refresh).await?;
+            return Ok(list_models(
+                &catalog,
+                search.as_deref(),
+                *ids,
+                &mut io::stdout(),
+            )?);
+        }
+        Some(Command::Completions { shell }) => {
+            return Ok(completions::write_registration(*shell, &mut io::stdout())?)
+        }
+        Some(Command::Man) => return Ok(completions::write_man_page(&mut io::stdout())?),
+        Some(Command::Pipe { name: None, .. }) => {
+            return Ok(list_pipelines(&config, &mut io::stdout())?)
+        }
+        _ => {}
+    }
+
+    if let Some(cache) = ModelCache::from_env() {
+        check_model(&cli, &config, &cache)?;
+    }
+    let provider = FallbackProvider::new(
+        build_provider(&cli, &config)?,
+        model_chain(&cli, &config, None),
+    );
+    if let Some(Command::Serve { port, host }) = &cli.command {
+        return run_server(&cli, &config, host, *port, Arc::new(provider)).await;
+    }
+    if let Some(Command::Sh { request }) = &cli.command {
+        let model = model_chain(&cli, &config, None).remove(0);
+        return shell::run(
+            &request.join(" "),
+            &model,
+            &provider,
+            &mut io::stdin().lock(),
+            &mut io::stdout(),
+            &shell::RealExecutor,
+        )
+        .await;
+    }
+    if let Some(Command::Pipe {
+        name: Some(name),
+        input,
+    }) = &cli.command
+    {
+        let name = name.clone();
+        let cli = Cli {
+            context: input.clone(),
+            ..cli
+        };
+        let contents = handle_input(&cli, &config).await?;
+        return run_pipe(
+            &cli,
+            &config,
+            &name,
+            &contents,
+            &provider,
+            &mut io::stdout(),
+        )
+        .await;
+    }
+
+    let contents = match rerun_prompt {
+        Some(prompt) => prompt,
+        None => handle_input(&cli, &config).await?,
+    };
+
+    // Same as `run_with_provider`, but the run is also recorded in the history.
+    let report = process_contents(&cli, &config, &contents, &provider, &mut io::stdout()).await?;
+    if let Some(report) = report {
+        if let Some(history) = &history {
+            record_history(&cli, &config, history, &contents, &report)?;
+        }
+        copy_answer(&cli, &report);
+        check_finished(&report)?;
+    }
+    Ok(())
+}
+
+/// Runs the CLI against the given provider, writing the answer to `out`.
+pub async fn run_with_provider(
+    cli: Cli,
+    config: &Config,
+    provider: &dyn Provider,
+    out: &mut (dyn Write + Send),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cli = resolve_aliases(cli, config);
+    let provider = FallbackProvider::new(provider, model_chain(&cli, config, None));
+    let contents = handle_input(&cli, config).await?;
+    if let Some(report) = process_contents(&cli, config, &contents, &provider, out).await? {
+        copy_answer(&cli, &report);
+        check_finished(&report)?;
+    }
+    Ok(())
 }
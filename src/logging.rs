@@ -0,0 +1,147 @@
+use atty::Stream;
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{InitError, RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{EnvFilter, ParseError};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::TryInitError;
+
+/// Target of the HTTP requests and responses logged with `--log-http`.
+pub const HTTP_TARGET: &str = "mergil::http";
+
+/// How many daily log files `--log-file` keeps.
+const MAX_LOG_FILES: usize = 7;
+
+/// What to log and where, from the command line.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LogOptions {
+    /// 0 logs warnings, 1 (`-v`) info, 2 (`-vv`) debug, 3 and up trace
+    pub verbosity: u8,
+    /// Log to this file, rotated daily, instead of stderr
+    pub file: Option<PathBuf>,
+    /// Log HTTP request and response bodies
+    pub http: bool,
+    /// Log errors only, whatever `verbosity` says
+    pub quiet: bool,
+}
+
+#[derive(Debug)]
+pub enum LoggingError {
+    InvalidFilter(String, ParseError),
+    FileFailed(PathBuf, InitError),
+    InitFailed(TryInitError),
+}
+
+impl std::fmt::Display for LoggingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoggingError::InvalidFilter(filter, e) => {
+                write!(f, "Invalid RUST_LOG filter `{}`: {}", filter, e)
+            }
+            LoggingError::FileFailed(path, e) => {
+                write!(f, "Failed to open log file {}: {}", path.display(), e)
+            }
+            LoggingError::InitFailed(e) => write!(f, "Failed to set up logging: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoggingError {}
+
+/// The filter used when `RUST_LOG` is not set.
+pub fn default_directives(verbosity: u8) -> &'static str {
+    match verbosity {
+        0 => "warn",
+        1 => "warn,mergil=info",
+        2 => "warn,mergil=debug",
+        _ => "info,mergil=trace",
+    }
+}
+
+/// `RUST_LOG` if set, else the filter for the options. HTTP bodies are only
+/// logged with `http`, whatever `RUST_LOG` says, since they hold the prompts.
+pub fn filter(options: &LogOptions, rust_log: Option<&str>) -> Result<EnvFilter, LoggingError> {
+    let filter = match rust_log.filter(|directives| !directives.trim().is_empty()) {
+        Some(directives) => EnvFilter::builder()
+            .parse(directives)
+            .map_err(|e| LoggingError::InvalidFilter(directives.to_string(), e))?,
+        None if options.quiet => EnvFilter::new("error"),
+        None => EnvFilter::new(default_directives(options.verbosity)),
+    };
+    let level = if options.http { "trace" } else { "off" };
+    let http = format!("{}={}", HTTP_TARGET, level)
+        .parse()
+        .expect("the HTTP directive is valid");
+    Ok(filter.add_directive(http))
+}
+
+/// A subscriber writing to `writer`, with the time taken by each span logged
+/// when it closes.
+pub fn subscriber<W>(filter: EnvFilter, writer: W, ansi: bool) -> impl Subscriber + Send + Sync
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(ansi)
+        .with_span_events(FmtSpan::CLOSE)
+        .finish()
+}
+
+/// Sets up logging for the process. Keep the returned guard until exit, so
+/// buffered lines reach the log file.
+pub fn init(options: &LogOptions) -> Result<Option<WorkerGuard>, LoggingError> {
+    let rust_log = std::env::var("RUST_LOG").ok();
+    let filter = filter(options, rust_log.as_deref())?;
+    let Some(path) = &options.file else {
+        let subscriber = subscriber(filter, io::stderr, atty::is(Stream::Stderr));
+        set_global(subscriber)?;
+        return Ok(None);
+    };
+
+    let (writer, guard) = tracing_appender::non_blocking(rolling_file(path)?);
+    set_global(subscriber(filter, writer, false))?;
+    Ok(Some(guard))
+}
+
+/// Daily files named `<path>.YYYY-MM-DD`, of which the last `MAX_LOG_FILES`
+/// are kept.
+fn rolling_file(path: &Path) -> Result<RollingFileAppender, LoggingError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(name.to_string_lossy())
+        .max_log_files(MAX_LOG_FILES)
+        .build(dir)
+        .map_err(|e| LoggingError::FileFailed(path.to_path_buf(), e))
+}
+
+fn set_global(subscriber: impl Subscriber + Send + Sync) -> Result<(), LoggingError> {
+    use tracing_subscriber::util::SubscriberInitExt;
+    subscriber.try_init().map_err(LoggingError::InitFailed)
+}
+
+/// One `name: value` line per header, with the Authorization value replaced.
+pub fn format_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if name == AUTHORIZATION {
+                "[REDACTED]".into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes())
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,8 +1,54 @@
-use clap::Parser;
-use mergil::common::Cli;
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use mergil::common::{Cli, Command};
+use mergil::completions::COMPLETE_ENV;
+use mergil::interrupt;
+use mergil::logging::{self, LogOptions};
+use mergil::output::{self, OutputFormat};
 use mergil::run;
+use std::process;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    run(Cli::parse()).await
+async fn main() {
+    CompleteEnv::with_factory(Cli::command)
+        .var(COMPLETE_ENV)
+        .complete();
+    let cli = Cli::parse();
+    let format = cli.output;
+    // The server logs each request, which is info level.
+    let min_verbosity = match cli.command {
+        Some(Command::Serve { .. }) => 1,
+        _ => 0,
+    };
+    let log_options = LogOptions {
+        verbosity: if cli.debug {
+            cli.verbose.max(2)
+        } else {
+            cli.verbose.max(min_verbosity)
+        },
+        file: cli.log_file.clone(),
+        http: cli.log_http,
+        quiet: cli.quiet,
+    };
+    let result = match logging::init(&log_options) {
+        Ok(log_guard) => {
+            interrupt::install();
+            let result = run(cli).await;
+            // Flushes the log file before exiting.
+            drop(log_guard);
+            result
+        }
+        Err(e) => Err(e.into()),
+    };
+
+    if let Err(e) = result {
+        if output::is_broken_pipe(&*e) {
+            return;
+        }
+        match format {
+            OutputFormat::Text => eprintln!("Error: {}", e),
+            OutputFormat::Json | OutputFormat::Ndjson => eprintln!("{}", output::error_json(&*e)),
+        }
+        process::exit(output::exit_code(&*e));
+    }
 }
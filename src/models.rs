@@ -0,0 +1,355 @@
+use crate::api::ApiError;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How long a fetched model list is used before it is fetched again.
+pub const CACHE_MAX_AGE_SECS: u64 = 24 * 60 * 60;
+
+/// How many "did you mean" suggestions are offered for an unknown model.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A model offered by the provider.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    /// Context window in tokens
+    pub context_length: Option<u64>,
+    /// USD per million input tokens
+    pub prompt_price: Option<f64>,
+    /// USD per million output tokens
+    pub completion_price: Option<f64>,
+    /// e.g. `text+image->text`
+    pub modality: Option<String>,
+}
+
+/// The model list of one provider, as cached on disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Catalog {
+    /// The API root the list was fetched from
+    pub source: String,
+    /// Seconds since the Unix epoch
+    pub fetched: u64,
+    pub models: Vec<Model>,
+}
+
+impl Catalog {
+    /// Whether the catalog was fetched from `source` less than
+    /// `CACHE_MAX_AGE_SECS` before `now`.
+    pub fn is_fresh(&self, source: &str, now: u64) -> bool {
+        self.source == source && now.saturating_sub(self.fetched) < CACHE_MAX_AGE_SECS
+    }
+
+    pub fn contains(&self, model: &str) -> bool {
+        let base = model.split(':').next().unwrap_or(model);
+        self.models.iter().any(|m| m.id == model || m.id == base)
+    }
+
+    /// The context window of `model` in tokens, if the catalog lists it.
+    pub fn context_length(&self, model: &str) -> Option<usize> {
+        let model = self.find(model)?;
+        model.context_length.map(|tokens| tokens as usize)
+    }
+
+    /// Whether `model` takes image input, if the catalog lists its modality.
+    pub fn accepts_images(&self, model: &str) -> Option<bool> {
+        let modality = self.find(model)?.modality.as_deref()?;
+        let input = modality.split("->").next().unwrap_or(modality);
+        Some(input.split('+').any(|kind| kind == "image"))
+    }
+
+    /// The entry for `model`, or for its base id without a `:` suffix.
+    fn find(&self, model: &str) -> Option<&Model> {
+        let base = model.split(':').next().unwrap_or(model);
+        self.models
+            .iter()
+            .find(|m| m.id == model)
+            .or_else(|| self.models.iter().find(|m| m.id == base))
+    }
+
+    /// Models whose id contains `query`, ignoring case.
+    pub fn search(&self, query: &str) -> Vec<&Model> {
+        let query = query.to_lowercase();
+        self.models
+            .iter()
+            .filter(|m| m.id.to_lowercase().contains(&query))
+            .collect()
+    }
+
+    /// The ids closest to `model`, best first, for a "did you mean" hint. A
+    /// missing vendor prefix such as `anthropic/` does not count as a typo.
+    pub fn suggest(&self, model: &str) -> Vec<String> {
+        let model = model.to_lowercase();
+        let limit = (model.chars().count() / 3).max(2);
+        let mut scored: Vec<(usize, &str)> = self
+            .models
+            .iter()
+            .map(|m| {
+                let id = m.id.to_lowercase();
+                let name = id.rsplit('/').next().unwrap_or(&id);
+                let distance = levenshtein(&model, &id).min(levenshtein(&model, name));
+                (distance, m.id.as_str())
+            })
+            .filter(|&(distance, _)| distance <= limit)
+            .collect();
+        scored.sort();
+        scored
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, id)| id.to_string())
+            .collect()
+    }
+
+    /// Fails with suggestions when `model` is not in the catalog.
+    pub fn validate(&self, model: &str) -> Result<(), ModelsError> {
+        if self.contains(model) {
+            return Ok(());
+        }
+        Err(ModelsError::Unknown {
+            model: model.to_string(),
+            suggestions: self.suggest(model),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ModelsError {
+    ReadFailed(PathBuf, io::Error),
+    WriteFailed(PathBuf, io::Error),
+    InvalidCache(PathBuf, serde_json::Error),
+    Unknown {
+        model: String,
+        suggestions: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for ModelsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelsError::ReadFailed(path, e) => {
+                write!(f, "Failed to read model cache {}: {}", path.display(), e)
+            }
+            ModelsError::WriteFailed(path, e) => {
+                write!(f, "Failed to write model cache {}: {}", path.display(), e)
+            }
+            ModelsError::InvalidCache(path, e) => {
+                write!(f, "Invalid model cache {}: {}", path.display(), e)
+            }
+            ModelsError::Unknown { model, suggestions } => {
+                write!(f, "Unknown model {}.", model)?;
+                if !suggestions.is_empty() {
+                    write!(f, " Did you mean {}?", suggestions.join(", "))?;
+                }
+                write!(f, " Run `mergil models --refresh` if the model is new.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModelsError {}
+
+/// The cached catalog in `~/.cache/mergil/models.json`.
+pub struct ModelCache {
+    path: PathBuf,
+}
+
+impl ModelCache {
+    pub fn new(path: PathBuf) -> Self {
+        ModelCache { path }
+    }
+
+    pub fn from_env() -> Option<ModelCache> {
+        dirs::cache_dir().map(|dir| ModelCache::new(dir.join("mergil").join("models.json")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The cached catalog, or `None` when nothing was cached yet.
+    pub fn load(&self) -> Result<Option<Catalog>, ModelsError> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ModelsError::ReadFailed(self.path.clone(), e)),
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| ModelsError::InvalidCache(self.path.clone(), e))
+    }
+
+    pub fn save(&self, catalog: &Catalog) -> Result<(), ModelsError> {
+        let write_failed = |e| ModelsError::WriteFailed(self.path.clone(), e);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(write_failed)?;
+        }
+        let text = serde_json::to_string(catalog).expect("catalogs always serialize");
+        fs::write(&self.path, text).map_err(write_failed)
+    }
+}
+
+/// How an API lists its models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    /// `GET /models` returning `{"data": [...]}`, as OpenRouter and OpenAI do
+    OpenAi,
+    /// Ollama's `GET /api/tags` at the server root
+    Ollama,
+}
+
+impl ListFormat {
+    /// Ollama is recognized by its default port, 11434.
+    pub fn of(base_url: &str) -> ListFormat {
+        if base_url.contains(":11434") {
+            ListFormat::Ollama
+        } else {
+            ListFormat::OpenAi
+        }
+    }
+}
+
+/// Fetches the model list from the API under `base_url`.
+pub async fn fetch(
+    client: &Client,
+    base_url: &str,
+    api_key: &str,
+    format: ListFormat,
+) -> Result<Vec<Model>, ApiError> {
+    let base_url = base_url.trim_end_matches('/');
+    let url = match format {
+        ListFormat::OpenAi => format!("{}/models", base_url),
+        ListFormat::Ollama => format!("{}/api/tags", base_url.trim_end_matches("/v1")),
+    };
+
+    let mut request = client.get(url);
+    if !api_key.is_empty() {
+        request = request.bearer_auth(api_key);
+    }
+    let response = request.send().await.map_err(ApiError::RequestFailed)?;
+    let status = response.status().as_u16();
+    let body = response.text().await.map_err(ApiError::RequestFailed)?;
+    match status {
+        200..=299 => {}
+        401 | 403 => return Err(ApiError::Unauthorized(body)),
+        429 => return Err(ApiError::RateLimited(body)),
+        _ => return Err(ApiError::ApiErrorResponse(body)),
+    }
+
+    match format {
+        ListFormat::OpenAi => parse_openai(&body),
+        ListFormat::Ollama => parse_ollama(&body),
+    }
+    .map_err(ApiError::ResponseParseFailed)
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ListedModel>,
+}
+
+#[derive(Deserialize)]
+struct ListedModel {
+    id: String,
+    context_length: Option<u64>,
+    pricing: Option<Pricing>,
+    architecture: Option<Architecture>,
+}
+
+/// OpenRouter prices are strings of USD per token.
+#[derive(Deserialize)]
+struct Pricing {
+    prompt: Option<String>,
+    completion: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Architecture {
+    modality: Option<String>,
+}
+
+/// Parses an OpenAI-style `{"data": [...]}` list, with OpenRouter's context
+/// length, pricing and modality when present.
+pub fn parse_openai(body: &str) -> Result<Vec<Model>, serde_json::Error> {
+    let list: ModelList = serde_json::from_str(body)?;
+    let per_million = |price: Option<&String>| {
+        price
+            .and_then(|p| p.parse::<f64>().ok())
+            .map(|p| p * 1_000_000.0)
+    };
+    Ok(list
+        .data
+        .into_iter()
+        .map(|m| Model {
+            id: m.id,
+            context_length: m.context_length,
+            prompt_price: per_million(m.pricing.as_ref().and_then(|p| p.prompt.as_ref())),
+            completion_price: per_million(m.pricing.as_ref().and_then(|p| p.completion.as_ref())),
+            modality: m.architecture.and_then(|a| a.modality),
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaModel>,
+}
+
+#[derive(Deserialize)]
+struct OllamaModel {
+    name: String,
+}
+
+/// Parses Ollama's `/api/tags`. Local models cost nothing.
+pub fn parse_ollama(body: &str) -> Result<Vec<Model>, serde_json::Error> {
+    let tags: OllamaTags = serde_json::from_str(body)?;
+    Ok(tags
+        .models
+        .into_iter()
+        .map(|m| Model {
+            id: m.name,
+            context_length: None,
+            prompt_price: Some(0.0),
+            completion_price: Some(0.0),
+            modality: None,
+        })
+        .collect())
+}
+
+/// Edit distance between `a` and `b`, in characters.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// A token count as `200k` or `1M`.
+pub fn format_tokens(tokens: u64) -> String {
+    if tokens >= 1_000_000 && tokens.is_multiple_of(1_000_000) {
+        format!("{}M", tokens / 1_000_000)
+    } else if tokens >= 1000 {
+        format!("{}k", (tokens + 500) / 1000)
+    } else {
+        tokens.to_string()
+    }
+}
+
+/// A price per million tokens as `$3.00`, or `free`.
+pub fn format_price(price: Option<f64>) -> String {
+    match price {
+        Some(0.0) => "free".to_string(),
+        Some(price) if price < 0.0 => "varies".to_string(),
+        Some(price) => format!("${:.2}", price),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,161 @@
+use crate::api::{ApiError, Completion, Usage};
+use crate::config::ConfigError;
+use crate::context::ContextError;
+use crate::history::HistoryError;
+use crate::image::ImageError;
+use crate::logging::LoggingError;
+use crate::models::ModelsError;
+use crate::pipeline::PipelineError;
+use crate::prompts::PromptError;
+use crate::schema::SchemaError;
+use crate::template::TemplateError;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::error::Error;
+use std::io;
+
+/// How the answer is written to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// The answer as text, rendered when `--markdown` is set
+    #[default]
+    Text,
+    /// One JSON object with the answer and its metadata
+    Json,
+    /// One JSON event per line: `delta` events while streaming, then `done`
+    Ndjson,
+}
+
+/// The answer and its metadata, as emitted by `--output json`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub content: String,
+    pub model: Option<String>,
+    pub usage: Option<Usage>,
+    pub finish_reason: Option<String>,
+    pub latency_ms: u64,
+    pub session_id: String,
+}
+
+impl Report {
+    pub fn new(completion: Completion, latency_ms: u64, session_id: &str) -> Self {
+        Report {
+            content: completion.content,
+            model: completion.model,
+            usage: completion.usage,
+            finish_reason: completion.finish_reason,
+            latency_ms,
+            session_id: session_id.to_string(),
+        }
+    }
+}
+
+/// A line of `--output ndjson`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event<'a> {
+    Delta { content: &'a str },
+    Done(&'a Report),
+}
+
+/// A stable identifier for the kind of error, so wrappers can branch on
+/// failures without parsing messages.
+pub fn error_code(error: &(dyn Error + 'static)) -> &'static str {
+    if let Some(e) = error.downcast_ref::<ApiError>() {
+        e.code()
+    } else if let Some(e) = error.downcast_ref::<PipelineError>() {
+        match e {
+            PipelineError::StepFailed(_, e) => e.code(),
+            _ => "pipeline",
+        }
+    } else if let Some(e) = error.downcast_ref::<ModelsError>() {
+        match e {
+            ModelsError::Unknown { .. } => "unknown_model",
+            _ => "models",
+        }
+    } else if error.is::<ConfigError>() {
+        "config"
+    } else if error.is::<TemplateError>() {
+        "template"
+    } else if error.is::<PromptError>() {
+        "prompt"
+    } else if error.is::<SchemaError>() {
+        "schema"
+    } else if error.is::<ImageError>() {
+        "image"
+    } else if error.is::<ContextError>() {
+        "context_length"
+    } else if error.is::<HistoryError>() {
+        "history"
+    } else if error.is::<LoggingError>() {
+        "logging"
+    } else if error.is::<regex::Error>() {
+        "invalid_pattern"
+    } else if error.is::<std::io::Error>() {
+        "io"
+    } else {
+        "error"
+    }
+}
+
+/// Exit status for errors without a more specific one.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit status for invalid arguments, including unknown models, templates,
+/// personas and pipelines.
+pub const EXIT_USAGE: i32 = 2;
+/// Exit status for a missing or rejected API key.
+pub const EXIT_AUTH: i32 = 3;
+/// Exit status when the API is rate limiting.
+pub const EXIT_RATE_LIMITED: i32 = 4;
+/// Exit status when the API could not be reached.
+pub const EXIT_NETWORK: i32 = 5;
+/// Exit status when a run is cancelled with Ctrl-C.
+pub const EXIT_CANCELLED: i32 = 6;
+/// Exit status when the answer was printed but cut off at the output limit.
+pub const EXIT_TRUNCATED: i32 = 7;
+
+/// The exit status for `error`, so scripts can tell failures apart.
+pub fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    let api_error = match error.downcast_ref::<PipelineError>() {
+        Some(PipelineError::StepFailed(_, e)) => Some(e),
+        _ => error.downcast_ref::<ApiError>(),
+    };
+    match api_error {
+        Some(ApiError::MissingApiKey(_) | ApiError::Unauthorized(_)) => EXIT_AUTH,
+        Some(ApiError::RateLimited(_)) => EXIT_RATE_LIMITED,
+        Some(ApiError::RequestFailed(_) | ApiError::RetryExhausted) => EXIT_NETWORK,
+        Some(ApiError::Cancelled) => EXIT_CANCELLED,
+        Some(ApiError::Truncated) => EXIT_TRUNCATED,
+        Some(_) => EXIT_FAILURE,
+        None if is_usage_error(error) => EXIT_USAGE,
+        None => EXIT_FAILURE,
+    }
+}
+
+/// Whether `error` is a write to a closed pipe, as when the output is piped
+/// into `head`. That is not a failure of the run, so it exits quietly.
+pub fn is_broken_pipe(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+fn is_usage_error(error: &(dyn Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref(),
+        Some(TemplateError::NotFound(..) | TemplateError::MissingVariable(_))
+    ) || matches!(error.downcast_ref(), Some(PromptError::UnknownPersona(..)))
+        || matches!(error.downcast_ref(), Some(PipelineError::NotFound(..)))
+        || matches!(error.downcast_ref(), Some(ModelsError::Unknown { .. }))
+}
+
+/// The JSON written to stderr for failures in the `json` and `ndjson` modes.
+pub fn error_json(error: &(dyn Error + 'static)) -> serde_json::Value {
+    serde_json::json!({
+        "type": "error",
+        "error": {
+            "code": error_code(error),
+            "message": error.to_string(),
+        }
+    })
+}
@@ -0,0 +1,371 @@
+use crate::api::{ApiError, Completion};
+use crate::config::{StepConfig, StepInput, StepOutput};
+use crate::markdown;
+use crate::prompts::{self, SystemPrompts};
+use crate::provider::{ChatRequest, Provider};
+use crate::schema;
+use async_trait::async_trait;
+use futures::future;
+use std::collections::HashMap;
+use std::io::Write;
+use tracing::debug;
+
+/// One step applied to an answer after it was produced, e.g. a round of
+/// self-refinement. Stages are chained with `Pipeline`.
+#[async_trait]
+pub trait Stage: Send + Sync {
+    /// Returns a new answer to `request`, starting from `answer`.
+    async fn run(
+        &self,
+        provider: &dyn Provider,
+        request: &ChatRequest,
+        answer: Completion,
+    ) -> Result<Completion, ApiError>;
+}
+
+/// Stages run in order, each receiving the previous stage's answer.
+///
+/// ```
+/// use mergil::pipeline::{Pipeline, Refine};
+///
+/// let pipeline = Pipeline::new().then(Refine::new("meta-llama/llama-3.1-405b", 2));
+/// ```
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn then(mut self, stage: impl Stage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Answers `request`, then passes the answer through every stage.
+    pub async fn run(
+        &self,
+        provider: &dyn Provider,
+        request: &ChatRequest,
+    ) -> Result<Completion, ApiError> {
+        let answer = provider.complete(request, &mut |_: &str| {}).await?;
+        self.apply(provider, request, answer).await
+    }
+
+    /// Passes an existing `answer` to `request` through every stage.
+    pub async fn apply(
+        &self,
+        provider: &dyn Provider,
+        request: &ChatRequest,
+        mut answer: Completion,
+    ) -> Result<Completion, ApiError> {
+        for stage in &self.stages {
+            answer = stage.run(provider, request, answer).await?;
+        }
+        Ok(answer)
+    }
+}
+
+/// Critique and revise: a critic model reviews the answer against the request
+/// and the request's model rewrites it, for up to `rounds` rounds or until the
+/// critic approves.
+pub struct Refine {
+    critic_model: String,
+    rounds: u32,
+}
+
+impl Refine {
+    pub fn new(critic_model: impl Into<String>, rounds: u32) -> Self {
+        Refine {
+            critic_model: critic_model.into(),
+            rounds,
+        }
+    }
+
+    async fn critique(
+        &self,
+        provider: &dyn Provider,
+        request: &ChatRequest,
+        answer: &str,
+    ) -> Result<String, ApiError> {
+        let critique_request = ChatRequest {
+            model: self.critic_model.clone(),
+            system: vec![prompts::REFINE_CRITIC.to_string()],
+            contents: vec![
+                format!("Request:\n\n{}", request.contents.join("\n\n")),
+                format!("Answer:\n\n{}", answer),
+            ],
+            ..ChatRequest::default()
+        };
+        let completion = provider
+            .complete(&critique_request, &mut |_: &str| {})
+            .await?;
+        Ok(completion.content)
+    }
+}
+
+#[async_trait]
+impl Stage for Refine {
+    async fn run(
+        &self,
+        provider: &dyn Provider,
+        request: &ChatRequest,
+        mut answer: Completion,
+    ) -> Result<Completion, ApiError> {
+        for round in 1..=self.rounds {
+            let critique = self.critique(provider, request, &answer.content).await?;
+            let approved = is_approval(&critique);
+            debug!("Refine round {} critique: {}", round, critique.trim());
+            if approved {
+                break;
+            }
+
+            let mut revise_request = request.clone();
+            revise_request.contents.push(
+                prompts::REFINE_REVISE
+                    .replace("{answer}", answer.content.trim())
+                    .replace("{critique}", critique.trim()),
+            );
+            answer = provider
+                .complete(&revise_request, &mut |_: &str| {})
+                .await?;
+            debug!("Refine round {} revision: {}", round, answer.content.trim());
+        }
+
+        Ok(answer)
+    }
+}
+
+/// Whether the critic accepted the answer as it is.
+fn is_approval(critique: &str) -> bool {
+    critique
+        .trim()
+        .trim_start_matches(['*', '#', ' '])
+        .starts_with("APPROVED")
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    NotFound(String, Vec<String>),
+    NoSteps(String),
+    DuplicateStep(String),
+    UnknownStep { step: String, after: String },
+    Cycle(String),
+    NoCode(String),
+    InvalidJson(String, serde_json::Error),
+    StepFailed(String, ApiError),
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::NotFound(name, available) if available.is_empty() => {
+                write!(f, "No pipeline named {}; none are configured", name)
+            }
+            PipelineError::NotFound(name, available) => write!(
+                f,
+                "No pipeline named {}; available: {}",
+                name,
+                available.join(", ")
+            ),
+            PipelineError::NoSteps(name) => write!(f, "Pipeline {} has no steps", name),
+            PipelineError::DuplicateStep(step) => {
+                write!(f, "More than one pipeline step is named {}", step)
+            }
+            PipelineError::UnknownStep { step, after } => {
+                write!(
+                    f,
+                    "Step {} runs after {}, which does not exist",
+                    step, after
+                )
+            }
+            PipelineError::Cycle(step) => {
+                write!(f, "Step {} depends on itself through `after`", step)
+            }
+            PipelineError::NoCode(step) => {
+                write!(f, "Step {} answered without a code block", step)
+            }
+            PipelineError::InvalidJson(step, e) => {
+                write!(f, "Step {} did not answer with JSON: {}", step, e)
+            }
+            PipelineError::StepFailed(step, e) => write!(f, "Step {} failed: {}", step, e),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+/// The name of each step, or its number when it has none.
+fn step_names(steps: &[StepConfig]) -> Vec<String> {
+    steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| step.name.clone().unwrap_or_else(|| (i + 1).to_string()))
+        .collect()
+}
+
+/// The order a config pipeline's steps run in.
+#[derive(Debug, PartialEq)]
+pub struct Plan {
+    /// Step indices; each wave runs after all steps of the earlier waves, and
+    /// the steps of one wave run at the same time
+    pub waves: Vec<Vec<usize>>,
+    /// Indices of the steps each step depends on
+    pub dependencies: Vec<Vec<usize>>,
+}
+
+/// Resolves the dependencies of `steps` and groups them into waves.
+pub fn plan(steps: &[StepConfig]) -> Result<Plan, PipelineError> {
+    let names = step_names(steps);
+    let mut index = HashMap::new();
+    for (i, name) in names.iter().enumerate() {
+        if index.insert(name.as_str(), i).is_some() {
+            return Err(PipelineError::DuplicateStep(name.clone()));
+        }
+    }
+
+    let mut dependencies = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        let after = match &step.after {
+            Some(after) => after
+                .iter()
+                .map(|after| {
+                    index
+                        .get(after.as_str())
+                        .copied()
+                        .ok_or_else(|| PipelineError::UnknownStep {
+                            step: names[i].clone(),
+                            after: after.clone(),
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None if i == 0 || step.input == StepInput::Original => Vec::new(),
+            None => vec![i - 1],
+        };
+        dependencies.push(after);
+    }
+
+    let mut wave_of: Vec<Option<usize>> = vec![None; steps.len()];
+    let mut waves: Vec<Vec<usize>> = Vec::new();
+    while wave_of.iter().any(Option::is_none) {
+        let ready: Vec<usize> = (0..steps.len())
+            .filter(|&i| wave_of[i].is_none())
+            .filter(|&i| dependencies[i].iter().all(|&d| wave_of[d].is_some()))
+            .collect();
+        if ready.is_empty() {
+            let stuck = wave_of.iter().position(Option::is_none).unwrap_or(0);
+            return Err(PipelineError::Cycle(names[stuck].clone()));
+        }
+        for &i in &ready {
+            wave_of[i] = Some(waves.len());
+        }
+        waves.push(ready);
+    }
+
+    Ok(Plan {
+        waves,
+        dependencies,
+    })
+}
+
+/// Runs config pipeline `steps` on `input` and returns the output of the last
+/// step. Steps of the same wave run concurrently; `default_model` answers
+/// steps without a model of their own. Progress is written to `progress`.
+pub async fn run_steps(
+    provider: &dyn Provider,
+    steps: &[StepConfig],
+    input: &[String],
+    default_model: &str,
+    progress: &mut (dyn Write + Send),
+) -> Result<String, PipelineError> {
+    let Plan {
+        waves,
+        dependencies,
+    } = plan(steps)?;
+    let names = step_names(steps);
+    let mut outputs: Vec<Option<String>> = vec![None; steps.len()];
+    let mut done = 0;
+
+    for wave in waves {
+        let requests: Vec<(usize, ChatRequest)> = wave
+            .iter()
+            .map(|&i| {
+                let step = &steps[i];
+                let previous = dependencies[i].iter().filter_map(|&d| outputs[d].clone());
+                // Steps that depend on nothing start from the input.
+                let contents = match step.input {
+                    _ if dependencies[i].is_empty() => input.to_vec(),
+                    StepInput::Original => input.to_vec(),
+                    StepInput::Previous => previous.collect(),
+                    StepInput::Both => input.iter().cloned().chain(previous).collect(),
+                };
+                let request = ChatRequest {
+                    model: step
+                        .model
+                        .clone()
+                        .unwrap_or_else(|| default_model.to_string()),
+                    system: step.system.iter().cloned().collect(),
+                    contents,
+                    ..ChatRequest::default()
+                };
+                (i, request)
+            })
+            .collect();
+
+        let answers = future::join_all(requests.iter().map(|(i, request)| async move {
+            let completion = provider.complete(request, &mut |_: &str| {}).await;
+            (*i, completion)
+        }))
+        .await;
+
+        for (i, completion) in answers {
+            let completion =
+                completion.map_err(|e| PipelineError::StepFailed(names[i].clone(), e))?;
+            outputs[i] = Some(extract(&names[i], steps[i].output, &completion.content)?);
+            done += 1;
+            let _ = writeln!(
+                progress,
+                "Finished step {} ({}/{})",
+                names[i],
+                done,
+                steps.len()
+            );
+        }
+    }
+
+    Ok(outputs.pop().flatten().unwrap_or_default())
+}
+
+/// The steps of `--preprocess`, run before the main request: the input is
+/// reformulated with the `preprocess` system prompt.
+pub fn preprocess_steps(prompts: &SystemPrompts) -> Vec<StepConfig> {
+    vec![StepConfig {
+        name: Some("preprocess".to_string()),
+        system: prompts.preprocess.clone(),
+        input: StepInput::Original,
+        ..StepConfig::default()
+    }]
+}
+
+/// Keeps the part of `answer` selected by `output`.
+fn extract(step: &str, output: StepOutput, answer: &str) -> Result<String, PipelineError> {
+    match output {
+        StepOutput::Text => Ok(answer.trim().to_string()),
+        StepOutput::Code => {
+            let blocks = markdown::code_blocks(answer);
+            if blocks.is_empty() {
+                return Err(PipelineError::NoCode(step.to_string()));
+            }
+            Ok(blocks.join("\n\n"))
+        }
+        StepOutput::Json => {
+            let value: serde_json::Value =
+                serde_json::from_str(schema::strip_code_fence(answer))
+                    .map_err(|e| PipelineError::InvalidJson(step.to_string(), e))?;
+            Ok(serde_json::to_string_pretty(&value).expect("JSON values always serialize"))
+        }
+    }
+}
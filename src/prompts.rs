@@ -0,0 +1,173 @@
+use crate::config::Config;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+pub const DEFAULT_SYSTEM: &str = "You are a helpful coding tool. You should keep
+    your answers brief, concise and mainly output code.";
+
+pub const DEFAULT_MARKDOWN: &str = "Please format your responses using Markdown syntax
+  for better readability. Use appropriate Markdown elements for headers,
+  lists, code blocks, and emphasis where applicable.";
+
+pub const DEFAULT_PLAIN: &str = "Answer without using markdown formatting!";
+
+pub const DEFAULT_PREPROCESS: &str = "Reformulate the user's submission into a clear and detailed instruction that captures the essence of what the user is asking for. Ensure that the reformulated instruction is phrased as if it were the user's original query or instruction, aiming to clarify any ambiguities and to provide a comprehensive understanding of the user's intent.  Prioritize maintaining all existing functionalities in the reformulated instruction unless the user explicitly requests the removal or modification of a specific feature. If necessary, add context or examples to enhance the clarity and specificity of the instruction.  If the user's submission is unclear or incomplete, attempt to infer the missing information or provide a helpful suggestion for clarification. Stay concise and avoid introducing unnecessary complexity or jargon, focusing on producing a reformulation that is easy to understand and actionable.";
+
+/// Used by `--overflow summarize` to condense the part of the input that was cut.
+pub const SUMMARIZE_CUT: &str = "The following text is the beginning of a longer input that had to be shortened. Summarize it so the rest of the input can still be understood: keep names, numbers, errors and decisions, drop repetition. Answer with the summary only.";
+
+/// Used by `--chunked` on each part of the input. `{request}`, `{part}` and
+/// `{parts}` are filled in.
+pub const CHUNK_MAP: &str = "You are reading part {part} of {parts} of a long input. The user's request about the whole input is:\n\n{request}\n\nExtract everything in this part that is relevant to the request, including names, numbers, errors and code, as concise notes. Do not answer the request itself. If nothing is relevant, answer with \"Nothing relevant.\"";
+
+/// Sent to the main model with the notes gathered by `--chunked`.
+pub const CHUNK_REDUCE: &str = "The input was too long to send at once, so it was split into parts and each part was condensed into notes. Answer the user's request using these notes as if you had read the whole input.";
+
+/// System prompt of the critic used by `--refine`.
+pub const REFINE_CRITIC: &str = "You review an answer written for a user's request. Check it for mistakes, missing parts and anything that does not do what was asked. If the answer fully and correctly satisfies the request, reply with exactly APPROVED. Otherwise list the concrete problems and how to fix them, without rewriting the answer.";
+
+/// Appended to the request when `--refine` asks for a revision. `{answer}` and
+/// `{critique}` are filled in.
+pub const REFINE_REVISE: &str = "Your previous answer was:\n\n{answer}\n\nA reviewer found these problems:\n\n{critique}\n\nWrite an improved answer to the original request that fixes them. Reply with the answer only.";
+
+/// Personas available without any configuration, as `(name, system prompt)`.
+pub const BUILTIN_PERSONAS: &[(&str, &str)] = &[
+    (
+        "reviewer",
+        "You are a meticulous senior code reviewer. Point out bugs, unsafe patterns, \
+         unclear naming and missing tests, ordered by severity. Suggest concrete fixes \
+         and keep praise short.",
+    ),
+    (
+        "shell",
+        "You are a shell expert. Answer with commands that can be pasted into a terminal, \
+         preferring portable POSIX tools, and add a one-line explanation only when the \
+         command is not obvious.",
+    ),
+    (
+        "explainer",
+        "You are a patient teacher. Explain the code or concept step by step in plain \
+         language, define jargon when it first appears and finish with a short summary.",
+    ),
+];
+
+/// The system prompts sent ahead of the user's input. `None` leaves that
+/// prompt out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemPrompts {
+    pub system: Option<String>,
+    pub markdown: Option<String>,
+    pub plain: Option<String>,
+    pub preprocess: Option<String>,
+}
+
+impl Default for SystemPrompts {
+    fn default() -> Self {
+        SystemPrompts {
+            system: Some(DEFAULT_SYSTEM.to_string()),
+            markdown: Some(DEFAULT_MARKDOWN.to_string()),
+            plain: Some(DEFAULT_PLAIN.to_string()),
+            preprocess: Some(DEFAULT_PREPROCESS.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PromptError {
+    UnknownPersona(String, Vec<String>),
+    ReadFailed(PathBuf, std::io::Error),
+}
+
+impl std::fmt::Display for PromptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptError::UnknownPersona(name, available) => write!(
+                f,
+                "Unknown persona '{}', available: {}",
+                name,
+                available.join(", ")
+            ),
+            PromptError::ReadFailed(path, e) => {
+                write!(f, "Failed to read system prompt {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PromptError {}
+
+impl SystemPrompts {
+    /// No system prompts at all, so only the user's messages are sent.
+    pub fn none() -> Self {
+        SystemPrompts {
+            system: None,
+            markdown: None,
+            plain: None,
+            preprocess: None,
+        }
+    }
+
+    /// Resolves the prompts from, in order of precedence: `--system`,
+    /// `--persona`, the config's `[prompts]` table and the built-in defaults.
+    pub fn resolve(
+        system: Option<&str>,
+        persona: Option<&str>,
+        config: &Config,
+    ) -> Result<SystemPrompts, PromptError> {
+        let prompts = &config.prompts;
+        let defaults = SystemPrompts::default();
+
+        let system = match (system, persona) {
+            (Some(system), _) => Some(read_prompt_arg(system)?),
+            (None, Some(persona)) => Some(find_persona(persona, &config.personas)?),
+            (None, None) => prompts.system.clone().or(defaults.system),
+        };
+
+        Ok(SystemPrompts {
+            system,
+            markdown: prompts.markdown.clone().or(defaults.markdown),
+            plain: prompts.plain.clone().or(defaults.plain),
+            preprocess: prompts.preprocess.clone().or(defaults.preprocess),
+        })
+    }
+
+    /// The system messages for a request, in the order they are sent.
+    pub fn messages(&self, markdown: bool) -> Vec<String> {
+        let format = if markdown {
+            &self.markdown
+        } else {
+            &self.plain
+        };
+        self.system.iter().chain(format).cloned().collect()
+    }
+}
+
+/// `--system` takes either the prompt itself or `@path` to read it from a file.
+fn read_prompt_arg(arg: &str) -> Result<String, PromptError> {
+    match arg.strip_prefix('@') {
+        Some(path) => {
+            fs::read_to_string(path).map_err(|e| PromptError::ReadFailed(PathBuf::from(path), e))
+        }
+        None => Ok(arg.to_string()),
+    }
+}
+
+/// Looks up a persona, preferring the config's `[personas]` over built-ins.
+fn find_persona(name: &str, configured: &HashMap<String, String>) -> Result<String, PromptError> {
+    if let Some(prompt) = configured.get(name) {
+        return Ok(prompt.clone());
+    }
+    if let Some((_, prompt)) = BUILTIN_PERSONAS.iter().find(|(n, _)| *n == name) {
+        return Ok(prompt.to_string());
+    }
+
+    let mut available: Vec<String> = BUILTIN_PERSONAS
+        .iter()
+        .map(|(n, _)| n.to_string())
+        .chain(configured.keys().cloned())
+        .collect();
+    available.sort();
+    available.dedup();
+    Err(PromptError::UnknownPersona(name.to_string(), available))
+}
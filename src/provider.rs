@@ -0,0 +1,346 @@
+pub use crate::api::ChatRequest;
+use crate::api::{self, ApiError, Completion};
+use async_trait::async_trait;
+use reqwest::Client;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Something that can answer chat completion requests. `run_with_provider`
+/// takes one of these so the CLI can be driven without the network.
+#[async_trait]
+pub trait Provider: Send + Sync {
+    /// Streams the completion for `request`, calling `on_delta` with each piece
+    /// of content as it arrives, and returns the full response with metadata.
+    async fn complete(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<Completion, ApiError>;
+
+    /// Like `complete`, but `on_delta` also gets the model the content comes
+    /// from, which differs from `request.model` once a fallback answers.
+    async fn complete_with_model(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str, &'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        let model = request.model.clone();
+        self.complete(request, &mut |delta: &str| on_delta(&model, delta))
+            .await
+    }
+
+    /// Whether requests may carry images. Whether the chosen model accepts
+    /// them is checked against the cached model catalog by the caller.
+    fn supports_images(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl<T: Provider + ?Sized> Provider for &T {
+    async fn complete(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        (**self).complete(request, on_delta).await
+    }
+
+    async fn complete_with_model(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str, &'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        (**self).complete_with_model(request, on_delta).await
+    }
+
+    fn supports_images(&self) -> bool {
+        (**self).supports_images()
+    }
+}
+
+/// Tries the next model of a fallback chain when a model of the chain is rate
+/// limited or unavailable before streaming anything. Once a model has streamed
+/// part of its answer, its error is returned as is, so answers are never mixed.
+/// Requests for models outside the chain are passed on as they are. Fallbacks
+/// are logged as warnings, and the model that answered at info level.
+pub struct FallbackProvider<P> {
+    inner: P,
+    chain: Vec<String>,
+}
+
+impl<P: Provider> FallbackProvider<P> {
+    pub fn new(inner: P, chain: Vec<String>) -> Self {
+        FallbackProvider { inner, chain }
+    }
+}
+
+/// Whether another model might answer where this one failed.
+pub fn should_fall_back(error: &ApiError) -> bool {
+    matches!(
+        error,
+        ApiError::RateLimited(_) | ApiError::ModelUnavailable(_) | ApiError::RetryExhausted
+    )
+}
+
+#[async_trait]
+impl<P: Provider> Provider for FallbackProvider<P> {
+    async fn complete(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        self.complete_with_model(request, &mut |_: &str, delta: &str| on_delta(delta))
+            .await
+    }
+
+    async fn complete_with_model(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str, &'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        let Some(start) = self.chain.iter().position(|m| *m == request.model) else {
+            return self.inner.complete_with_model(request, on_delta).await;
+        };
+
+        let mut request = request.clone();
+        for (i, model) in self.chain.iter().enumerate().skip(start) {
+            request.model = model.clone();
+            let mut streamed = false;
+            let mut forward = |delta: &str| {
+                streamed = true;
+                on_delta(model, delta);
+            };
+            match self.inner.complete(&request, &mut forward).await {
+                Ok(completion) => {
+                    if i > start {
+                        info!("Answered by {}", model);
+                    }
+                    return Ok(completion);
+                }
+                Err(e) if !streamed && should_fall_back(&e) && i + 1 < self.chain.len() => {
+                    warn!("{} failed ({}), trying {}", model, e, self.chain[i + 1]);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("the chain contains the requested model")
+    }
+
+    fn supports_images(&self) -> bool {
+        self.inner.supports_images()
+    }
+}
+
+/// Sends requests for models written `<name>:<model>` to the provider added
+/// as `name`, with the prefix removed, and all others to the default one.
+/// Other prefixes stay part of the model id, as in `qwen2.5:7b` or
+/// `meta-llama/llama-3.1-8b-instruct:free`.
+pub struct RoutingProvider<P> {
+    default: P,
+    routes: HashMap<String, P>,
+}
+
+impl<P: Provider> RoutingProvider<P> {
+    pub fn new(default: P) -> Self {
+        RoutingProvider {
+            default,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Sends models prefixed with `name:` to `provider`.
+    pub fn with_route(mut self, name: impl Into<String>, provider: P) -> Self {
+        self.routes.insert(name.into(), provider);
+        self
+    }
+}
+
+#[async_trait]
+impl<P: Provider> Provider for RoutingProvider<P> {
+    async fn complete(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        let route = request.model.split_once(':').and_then(|(name, model)| {
+            let provider = self.routes.get(name)?;
+            Some((name, model, provider))
+        });
+        let Some((name, model, provider)) = route else {
+            return self.default.complete(request, on_delta).await;
+        };
+
+        let request = ChatRequest {
+            model: model.to_string(),
+            ..request.clone()
+        };
+        let mut completion = provider.complete(&request, on_delta).await?;
+        completion.model = completion.model.map(|model| format!("{}:{}", name, model));
+        Ok(completion)
+    }
+
+    fn supports_images(&self) -> bool {
+        self.default.supports_images()
+    }
+}
+
+/// Talks to OpenRouter's OpenAI-compatible chat completions endpoint, or to any
+/// other server exposing the same API under `base_url`.
+pub struct OpenRouterProvider {
+    client: Client,
+    api_key: String,
+    chat_url: String,
+}
+
+impl OpenRouterProvider {
+    pub fn new(client: Client, api_key: String, base_url: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| api::DEFAULT_BASE_URL.to_string());
+        OpenRouterProvider {
+            client,
+            api_key,
+            chat_url: format!("{}/chat/completions", base_url.trim_end_matches('/')),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for OpenRouterProvider {
+    async fn complete(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        api::stream_api_request(
+            &self.client,
+            &self.api_key,
+            request,
+            Some(&self.chat_url),
+            on_delta,
+        )
+        .await
+    }
+
+    fn supports_images(&self) -> bool {
+        true
+    }
+}
+
+/// A provider that replays scripted responses in order, for tests.
+///
+/// ```
+/// use mergil::provider::MockProvider;
+/// use std::time::Duration;
+///
+/// let provider = MockProvider::new()
+///     .with_response("Hello, world!")
+///     .with_latency(Duration::from_millis(10));
+/// ```
+#[derive(Default)]
+pub struct MockProvider {
+    /// Content to stream, then the error to fail with, if any
+    script: Mutex<VecDeque<(String, Option<ApiError>)>>,
+    latency: Duration,
+    images: bool,
+    finish_reason: Option<String>,
+    requests: Mutex<Vec<ChatRequest>>,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        MockProvider::default()
+    }
+
+    /// Queues a successful response. It is streamed back one word at a time.
+    pub fn with_response(self, content: impl Into<String>) -> Self {
+        self.script
+            .lock()
+            .unwrap()
+            .push_back((content.into(), None));
+        self
+    }
+
+    /// Queues an error to be returned instead of a response.
+    pub fn with_error(self, error: ApiError) -> Self {
+        self.script
+            .lock()
+            .unwrap()
+            .push_back((String::new(), Some(error)));
+        self
+    }
+
+    /// Queues a response that streams `content` and then fails with `error`.
+    pub fn with_partial(self, content: impl Into<String>, error: ApiError) -> Self {
+        self.script
+            .lock()
+            .unwrap()
+            .push_back((content.into(), Some(error)));
+        self
+    }
+
+    /// Delays every reply, before anything is streamed.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Accepts requests with images; without this they are refused.
+    pub fn with_image_support(mut self) -> Self {
+        self.images = true;
+        self
+    }
+
+    /// Reports `reason` as the finish reason of every response, instead of
+    /// `stop`.
+    pub fn with_finish_reason(mut self, reason: impl Into<String>) -> Self {
+        self.finish_reason = Some(reason.into());
+        self
+    }
+
+    /// Returns every request received so far.
+    pub fn requests(&self) -> Vec<ChatRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Provider for MockProvider {
+    async fn complete(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let next = self.script.lock().unwrap().pop_front();
+
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+
+        let (content, error) = next.ok_or_else(|| {
+            ApiError::ApiErrorResponse("MockProvider has no scripted response left".to_string())
+        })?;
+        for delta in content.split_inclusive(' ') {
+            on_delta(delta);
+        }
+        if let Some(error) = error {
+            return Err(error);
+        }
+        Ok(Completion {
+            content,
+            model: Some(request.model.clone()),
+            usage: None,
+            finish_reason: Some(
+                self.finish_reason
+                    .clone()
+                    .unwrap_or_else(|| "stop".to_string()),
+            ),
+        })
+    }
+
+    fn supports_images(&self) -> bool {
+        self.images
+    }
+}
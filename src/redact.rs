@@ -0,0 +1,134 @@
+use regex::Regex;
+
+/// Built-in patterns as `(name, regex)`. When a pattern has a capture group,
+/// only the first group is replaced so that e.g. `.env` keys stay readable.
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    (
+        "private-key",
+        r"(?s)-----BEGIN [A-Z ]*PRIVATE KEY-----.*?-----END [A-Z ]*PRIVATE KEY-----",
+    ),
+    ("aws-access-key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+    (
+        "github-token",
+        r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})\b",
+    ),
+    (
+        "jwt",
+        r"\beyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+",
+    ),
+    ("api-key", r"\bsk-[A-Za-z0-9_-]{20,}"),
+    ("slack-token", r"\bxox[abposr]-[A-Za-z0-9-]{10,}"),
+    (
+        "auth-header",
+        r#"(?i)\bauthorization\s*:\s*(?:(?:bearer|basic|token)\s+)?([^\s"']+)"#,
+    ),
+    // Names are uppercase as in `.env` files, so source code assignments like
+    // `token = request.headers.get(...)` are left alone. The value is a quoted
+    // string, or the words up to a comment or the end of the line. `AUTH` must
+    // be a whole word, so `AUTHOR:` is left alone.
+    (
+        "env-secret",
+        r#"(?m)^\s*(?:export\s+)?(?:[A-Z0-9_]*(?:SECRET|TOKEN|PASSWORD|PASSWD|API_?KEY|ACCESS_?KEY|PRIVATE_?KEY|CREDENTIALS?)[A-Z0-9_]*|[A-Z0-9_]*AUTH(?:ORIZATION)?\b)\s*[=:]\s*(?:(?i:bearer|basic|token)\s+)?("[^"\n]*"|'[^'\n]*'|[^\s"'#]+(?:[ \t]+[^\s"'#]+)*)"#,
+    ),
+];
+
+struct Rule {
+    name: String,
+    regex: Regex,
+}
+
+/// How many matches of one kind of secret were replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redaction {
+    pub kind: String,
+    pub count: usize,
+}
+
+/// Replaces secrets in prompt contents with `[REDACTED:<kind>]` placeholders.
+pub struct Redactor {
+    rules: Vec<Rule>,
+}
+
+impl Redactor {
+    /// Builds a redactor from the built-in patterns plus `custom_patterns`,
+    /// which are reported as `custom`.
+    pub fn new(custom_patterns: &[String]) -> Result<Redactor, regex::Error> {
+        let builtin = BUILTIN_PATTERNS
+            .iter()
+            .map(|(name, pattern)| (name.to_string(), *pattern));
+        let custom = custom_patterns
+            .iter()
+            .map(|pattern| ("custom".to_string(), pattern.as_str()));
+
+        let rules = builtin
+            .chain(custom)
+            .map(|(name, pattern)| {
+                Ok(Rule {
+                    name,
+                    regex: Regex::new(pattern)?,
+                })
+            })
+            .collect::<Result<Vec<_>, regex::Error>>()?;
+
+        Ok(Redactor { rules })
+    }
+
+    /// Returns the redacted text and what was replaced, in rule order.
+    pub fn redact(&self, text: &str) -> (String, Vec<Redaction>) {
+        let mut text = text.to_string();
+        let mut redactions: Vec<Redaction> = Vec::new();
+
+        for rule in &self.rules {
+            let placeholder = format!("[REDACTED:{}]", rule.name);
+            let mut count = 0;
+            let mut redacted = String::with_capacity(text.len());
+            let mut last = 0;
+
+            for captures in rule.regex.captures_iter(&text) {
+                let secret = captures.get(1).or_else(|| captures.get(0)).unwrap();
+                if secret.as_str().starts_with("[REDACTED:") {
+                    continue;
+                }
+                redacted.push_str(&text[last..secret.start()]);
+                redacted.push_str(&placeholder);
+                last = secret.end();
+                count += 1;
+            }
+
+            if count > 0 {
+                redacted.push_str(&text[last..]);
+                text = redacted;
+                record(&mut redactions, &rule.name, count);
+            }
+        }
+
+        (text, redactions)
+    }
+
+    /// Redacts every entry, merging the reports.
+    pub fn redact_all(&self, contents: &[String]) -> (Vec<String>, Vec<Redaction>) {
+        let mut redactions: Vec<Redaction> = Vec::new();
+        let contents = contents
+            .iter()
+            .map(|content| {
+                let (content, found) = self.redact(content);
+                for redaction in found {
+                    record(&mut redactions, &redaction.kind, redaction.count);
+                }
+                content
+            })
+            .collect();
+
+        (contents, redactions)
+    }
+}
+
+fn record(redactions: &mut Vec<Redaction>, kind: &str, count: usize) {
+    match redactions.iter_mut().find(|r| r.kind == kind) {
+        Some(redaction) => redaction.count += count,
+        None => redactions.push(Redaction {
+            kind: kind.to_string(),
+            count,
+        }),
+    }
+}
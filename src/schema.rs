@@ -0,0 +1,152 @@
+use jsonschema::Validator;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A JSON Schema the answer must match, read from `--schema`.
+pub struct Schema {
+    name: String,
+    value: Value,
+    validator: Validator,
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    ReadFailed(PathBuf, io::Error),
+    InvalidJson(PathBuf, serde_json::Error),
+    InvalidSchema(String, String),
+    ValidationFailed(u32, Vec<String>),
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::ReadFailed(path, e) => {
+                write!(f, "Failed to read {}: {}", path.display(), e)
+            }
+            SchemaError::InvalidJson(path, e) => {
+                write!(f, "Schema {} is not valid JSON: {}", path.display(), e)
+            }
+            SchemaError::InvalidSchema(name, e) => {
+                write!(f, "Schema '{}' is not a valid JSON Schema: {}", name, e)
+            }
+            SchemaError::ValidationFailed(attempts, errors) => {
+                write!(
+                    f,
+                    "Response did not match the schema after {} attempts: {}",
+                    attempts,
+                    errors.join("; ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl Schema {
+    pub fn load(path: &Path) -> Result<Schema, SchemaError> {
+        let text = fs::read_to_string(path).map_err(|e| SchemaError::ReadFailed(path.into(), e))?;
+        let value =
+            serde_json::from_str(&text).map_err(|e| SchemaError::InvalidJson(path.into(), e))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Schema::from_value(&name, value)
+    }
+
+    /// Compiles `value`. `name` is sent to the API, so anything outside
+    /// `[A-Za-z0-9_-]` is replaced with `_`.
+    pub fn from_value(name: &str, value: Value) -> Result<Schema, SchemaError> {
+        let validator = jsonschema::validator_for(&value)
+            .map_err(|e| SchemaError::InvalidSchema(name.to_string(), e.to_string()))?;
+        let name: String = name
+            .chars()
+            .map(|c| match c {
+                'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '-' => c,
+                _ => '_',
+            })
+            .collect();
+
+        Ok(Schema {
+            name: if name.is_empty() {
+                "response".into()
+            } else {
+                name
+            },
+            value,
+            validator,
+        })
+    }
+
+    /// The `response_format` request field asking the API to enforce the schema.
+    pub fn response_format(&self) -> Value {
+        serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": self.name,
+                "schema": self.value,
+            }
+        })
+    }
+
+    /// A system prompt describing the schema, for APIs that reject
+    /// `response_format`.
+    pub fn instructions(&self) -> String {
+        format!(
+            "Respond with a single JSON value that matches this JSON Schema. \
+             Output only the JSON, without code fences or any other text.\n\n{}",
+            serde_json::to_string_pretty(&self.value).unwrap_or_default()
+        )
+    }
+
+    /// Parses `content` as JSON, ignoring a surrounding code fence, and checks it
+    /// against the schema. On failure returns one message per problem.
+    pub fn validate(&self, content: &str) -> Result<Value, Vec<String>> {
+        let instance: Value = serde_json::from_str(strip_code_fence(content))
+            .map_err(|e| vec![format!("not valid JSON: {}", e)])?;
+
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(&instance)
+            .map(|error| {
+                let path = error.instance_path().to_string();
+                let path = if path.is_empty() {
+                    "/".to_string()
+                } else {
+                    path
+                };
+                format!("{}: {}", path, error)
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(instance)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The follow-up message asking the model to fix an invalid `response`.
+    pub fn feedback(&self, response: &str, errors: &[String]) -> String {
+        format!(
+            "Your previous response did not match the JSON Schema.\n\n\
+             Response:\n{}\n\nErrors:\n- {}\n\n\
+             Respond again with only the corrected JSON.",
+            response.trim(),
+            errors.join("\n- ")
+        )
+    }
+}
+
+/// The body of a response wrapped in a single fenced code block.
+pub(crate) fn strip_code_fence(content: &str) -> &str {
+    let content = content.trim();
+    let Some(rest) = content.strip_prefix("```") else {
+        return content;
+    };
+    let rest = rest.split_once('\n').map_or("", |(_, body)| body);
+    rest.strip_suffix("```").unwrap_or(rest).trim()
+}
@@ -0,0 +1,382 @@
+use crate::api::{ApiError, Completion, ContentPart, Role, Turn};
+use crate::image::{Image, ImageError};
+use crate::provider::{ChatRequest, Provider};
+use crate::redact::Redactor;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Environment variable holding the bearer token clients must send.
+pub const TOKEN_ENV: &str = "MERGIL_SERVE_TOKEN";
+
+/// What the server needs besides the provider.
+pub struct ServerOptions {
+    /// Clients must send `Authorization: Bearer <token>`
+    pub token: String,
+    /// Used when a request names no model
+    pub default_model: String,
+    /// Model names a request may use instead of the full id
+    pub aliases: HashMap<String, String>,
+    /// Listed by `/v1/models`
+    pub models: Vec<String>,
+    /// Applied to user messages; `None` sends them as they are
+    pub redactor: Option<Redactor>,
+}
+
+struct AppState {
+    provider: Arc<dyn Provider>,
+    options: ServerOptions,
+}
+
+/// The OpenAI-compatible routes, answered by `provider`.
+pub fn router(provider: Arc<dyn Provider>, options: ServerOptions) -> Router {
+    let state = Arc::new(AppState { provider, options });
+    Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/models", get(models))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state)
+}
+
+/// Serves `router` on `listener` until the process is stopped.
+pub async fn serve(listener: TcpListener, router: Router) -> io::Result<()> {
+    axum::serve(listener, router).await
+}
+
+/// A random token for when `MERGIL_SERVE_TOKEN` is not set.
+pub fn generate_token() -> io::Result<String> {
+    let mut bytes = [0u8; 24];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+async fn authorize(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token == state.options.token);
+    if !authorized {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            "invalid_api_key",
+            "Missing or wrong bearer token",
+        );
+    }
+    next.run(request).await
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionRequest {
+    model: Option<String>,
+    messages: Vec<RequestMessage>,
+    #[serde(default)]
+    stream: bool,
+    temperature: Option<f64>,
+    response_format: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RequestMessage {
+    role: String,
+    content: MessageContent,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text of the message, its text parts joined by newlines, and the
+    /// URLs of its images.
+    fn split(&self) -> (String, Vec<&str>) {
+        match self {
+            MessageContent::Text(text) => (text.clone(), Vec::new()),
+            MessageContent::Parts(parts) => {
+                let mut texts = Vec::new();
+                let mut images = Vec::new();
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => texts.push(text.as_str()),
+                        ContentPart::ImageUrl { image_url } => images.push(image_url.url.as_str()),
+                    }
+                }
+                (texts.join("\n"), images)
+            }
+        }
+    }
+}
+
+/// Maps the OpenAI message list onto a `ChatRequest`. User messages after the
+/// last assistant message are the request's contents, and the messages before
+/// it its history. Images are only taken in the contents, as `data:` URLs, and
+/// only when the provider accepts them; the error says why a request is refused.
+fn chat_request(
+    request: &CompletionRequest,
+    options: &ServerOptions,
+    supports_images: bool,
+) -> Result<ChatRequest, String> {
+    let last_assistant = request.messages.iter().rposition(|m| m.role == "assistant");
+    let mut system = Vec::new();
+    let mut history = Vec::new();
+    let mut contents = Vec::new();
+    let mut images = Vec::new();
+    for (i, message) in request.messages.iter().enumerate() {
+        let (text, urls) = message.content.split();
+        let in_history = last_assistant.is_some_and(|last| i <= last);
+        let role = message.role.as_str();
+        if !urls.is_empty() {
+            if !supports_images {
+                return Err(ImageError::UnsupportedProvider.to_string());
+            }
+            if in_history || matches!(role, "system" | "developer") {
+                return Err(
+                    "Images are only accepted in user messages after the last assistant message"
+                        .to_string(),
+                );
+            }
+            for url in urls {
+                images.push(
+                    Image::from_data_url(url).ok_or("Images must be sent as base64 data: URLs")?,
+                );
+            }
+        }
+        match role {
+            "system" | "developer" => system.push(text),
+            "assistant" => history.push(Turn {
+                role: Role::Assistant,
+                content: text,
+            }),
+            _ if in_history => history.push(Turn {
+                role: Role::User,
+                content: text,
+            }),
+            _ => contents.push(text),
+        }
+    }
+    if let Some(redactor) = &options.redactor {
+        contents = redactor.redact_all(&contents).0;
+        for turn in history.iter_mut().filter(|turn| turn.role == Role::User) {
+            turn.content = redactor.redact(&turn.content).0;
+        }
+    }
+
+    Ok(ChatRequest {
+        model: match &request.model {
+            Some(model) => options.aliases.get(model).unwrap_or(model).clone(),
+            None => options.default_model.clone(),
+        },
+        system,
+        history,
+        contents,
+        temperature: request.temperature,
+        response_format: request.response_format.clone(),
+        images,
+    })
+}
+
+async fn models(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let data: Vec<Value> = state
+        .options
+        .models
+        .iter()
+        .map(|model| json!({ "id": model, "object": "model", "owned_by": "mergil" }))
+        .collect();
+    Json(json!({ "object": "list", "data": data }))
+}
+
+async fn chat_completions(State(state): State<Arc<AppState>>, body: String) -> Response {
+    let request: CompletionRequest = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &format!("Invalid request: {}", e),
+            )
+        }
+    };
+    if !request.messages.iter().any(|m| m.role != "system") {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "The request has no user messages",
+        );
+    }
+
+    let chat = match chat_request(&request, &state.options, state.provider.supports_images()) {
+        Ok(chat) => chat,
+        Err(message) => {
+            return error_response(StatusCode::BAD_REQUEST, "invalid_request_error", &message)
+        }
+    };
+    if request.stream {
+        stream_completion(state, chat)
+    } else {
+        complete(&state, &chat).await
+    }
+}
+
+async fn complete(state: &AppState, request: &ChatRequest) -> Response {
+    let started = Instant::now();
+    let result = state.provider.complete(request, &mut |_: &str| {}).await;
+    log_request(request, false, started, result.as_ref().err());
+
+    match result {
+        Ok(completion) => Json(completion_body(&request.model, &completion)).into_response(),
+        Err(e) => api_error_response(&e),
+    }
+}
+
+/// Streams the answer as server-sent events in the OpenAI chunk format, ending
+/// with `[DONE]`. Chunks name the model that answered, which after a fallback
+/// is not the requested one. Errors after the stream started are sent as an
+/// `error` event.
+fn stream_completion(state: Arc<AppState>, request: ChatRequest) -> Response {
+    let (sender, receiver) = mpsc::unbounded::<Value>();
+    let id = completion_id();
+
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let chunk = |model: &str, delta: Value, finish_reason: Option<&str>| {
+            json!({
+                "id": id,
+                "object": "chat.completion.chunk",
+                "created": now(),
+                "model": model,
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            })
+        };
+
+        // The role is sent with the first delta, once the answering model is
+        // known.
+        let mut answering: Option<String> = None;
+        let result = state
+            .provider
+            .complete_with_model(&request, &mut |model: &str, delta: &str| {
+                if answering.is_none() {
+                    answering = Some(model.to_string());
+                    let _ =
+                        sender.unbounded_send(chunk(model, json!({ "role": "assistant" }), None));
+                }
+                let _ = sender.unbounded_send(chunk(model, json!({ "content": delta }), None));
+            })
+            .await;
+        log_request(&request, true, started, result.as_ref().err());
+
+        let last = match result {
+            Ok(completion) => {
+                let model = completion
+                    .model
+                    .as_deref()
+                    .or(answering.as_deref())
+                    .unwrap_or(&request.model);
+                if answering.is_none() {
+                    let _ =
+                        sender.unbounded_send(chunk(model, json!({ "role": "assistant" }), None));
+                }
+                chunk(
+                    model,
+                    json!({}),
+                    Some(completion.finish_reason.as_deref().unwrap_or("stop")),
+                )
+            }
+            Err(e) => error_body(e.code(), &e.to_string()),
+        };
+        let _ = sender.unbounded_send(last);
+    });
+
+    let events = receiver
+        .map(|chunk| Event::default().data(chunk.to_string()))
+        .chain(futures::stream::once(async {
+            Event::default().data("[DONE]")
+        }))
+        .map(Ok::<_, Infallible>);
+    Sse::new(events).into_response()
+}
+
+fn completion_body(model: &str, completion: &Completion) -> Value {
+    let mut body = json!({
+        "id": completion_id(),
+        "object": "chat.completion",
+        "created": now(),
+        "model": completion.model.as_deref().unwrap_or(model),
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": completion.content },
+            "finish_reason": completion.finish_reason.as_deref().unwrap_or("stop"),
+        }],
+    });
+    if let Some(usage) = &completion.usage {
+        body["usage"] = json!(usage);
+    }
+    body
+}
+
+fn api_error_response(error: &ApiError) -> Response {
+    let status = match error {
+        ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        ApiError::ModelUnavailable(_) => StatusCode::NOT_FOUND,
+        ApiError::RequestFailed(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    error_response(status, error.code(), &error.to_string())
+}
+
+fn error_response(status: StatusCode, code: &str, message: &str) -> Response {
+    (status, Json(error_body(code, message))).into_response()
+}
+
+fn error_body(code: &str, message: &str) -> Value {
+    json!({ "error": { "message": message, "type": code, "code": code } })
+}
+
+/// One line per completion, at info level, or warn level when it failed.
+fn log_request(request: &ChatRequest, stream: bool, started: Instant, error: Option<&ApiError>) {
+    let elapsed = started.elapsed().as_millis();
+    match error {
+        None => info!(
+            "POST /v1/chat/completions model={} stream={} {}ms ok",
+            request.model, stream, elapsed
+        ),
+        Some(e) => warn!(
+            "POST /v1/chat/completions model={} stream={} {}ms error: {}",
+            request.model, stream, elapsed, e
+        ),
+    }
+}
+
+fn completion_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("chatcmpl-{:x}", nanos)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,383 @@
+use crate::clipboard;
+use crate::common::complete_with_cancellation;
+use crate::output::OutputFormat;
+use crate::provider::{ChatRequest, Provider};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process::Command;
+use tracing::warn;
+
+/// Programs that run the rest of their arguments as a command, with their
+/// options that take a separate value.
+const WRAPPERS: &[(&str, &[&str])] = &[
+    (
+        "sudo",
+        &[
+            "-u",
+            "--user",
+            "-g",
+            "--group",
+            "-h",
+            "--host",
+            "-p",
+            "--prompt",
+            "-C",
+            "--close-from",
+            "-D",
+            "--chdir",
+            "-r",
+            "--role",
+            "-t",
+            "--type",
+            "-U",
+            "--other-user",
+            "-T",
+            "--command-timeout",
+        ],
+    ),
+    ("doas", &["-u", "-C"]),
+    (
+        "env",
+        &["-u", "--unset", "-C", "--chdir", "-S", "--split-string"],
+    ),
+    (
+        "xargs",
+        &[
+            "-a",
+            "--arg-file",
+            "-d",
+            "--delimiter",
+            "-E",
+            "-I",
+            "-L",
+            "--max-lines",
+            "-n",
+            "--max-args",
+            "-P",
+            "--max-procs",
+            "-s",
+            "--max-chars",
+        ],
+    ),
+];
+
+/// Shells whose `-c` argument is a command line of its own.
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish"];
+
+/// `find` actions that run the command following them.
+const FIND_ACTIONS: &[&str] = &["-exec", "-execdir", "-ok", "-okdir"];
+
+/// Git options, before the subcommand, that take a separate value.
+const GIT_OPTIONS: &[&str] = &["-C", "-c", "--git-dir", "--work-tree", "--namespace"];
+
+/// A suggested command and why it does what was asked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub command: String,
+    pub explanation: String,
+}
+
+/// Runs a suggested command. `RealExecutor` hands it to `$SHELL -c`.
+pub trait CommandExecutor {
+    fn execute(&self, command: &str) -> io::Result<Option<i32>>;
+}
+
+pub struct RealExecutor;
+
+impl CommandExecutor for RealExecutor {
+    fn execute(&self, command: &str) -> io::Result<Option<i32>> {
+        let status = Command::new(user_shell()).arg("-c").arg(command).status()?;
+        Ok(status.code())
+    }
+}
+
+fn user_shell() -> String {
+    env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+}
+
+/// Describes the OS, preferring the distribution name from `/etc/os-release`.
+fn detect_os() -> String {
+    let pretty_name = fs::read_to_string("/etc/os-release").ok().and_then(|text| {
+        text.lines()
+            .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+            .map(|name| name.trim_matches('"').to_string())
+    });
+
+    match pretty_name {
+        Some(name) => format!("{} ({})", name, env::consts::OS),
+        None => env::consts::OS.to_string(),
+    }
+}
+
+/// The system prompt for shell mode, describing the user's environment.
+pub fn system_prompt() -> String {
+    format!(
+        "You translate requests into a single shell command. The user runs {} with the \
+         shell {}. Reply with exactly two lines and nothing else:\n\
+         COMMAND: <one command line, using pipes or && if needed>\n\
+         EXPLANATION: <one sentence describing what the command does>",
+        detect_os(),
+        user_shell()
+    )
+}
+
+/// Parses a reply in the `COMMAND:`/`EXPLANATION:` format. Replies that ignore
+/// the format are taken as the command itself, minus any code fence.
+pub fn parse_suggestion(response: &str) -> Suggestion {
+    let mut command = None;
+    let mut explanation = String::new();
+    for line in response.lines() {
+        if let Some(rest) = line.trim().strip_prefix("COMMAND:") {
+            command = Some(rest.trim().trim_matches('`').to_string());
+        } else if let Some(rest) = line.trim().strip_prefix("EXPLANATION:") {
+            explanation = rest.trim().to_string();
+        }
+    }
+
+    let command = command.unwrap_or_else(|| {
+        response
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("```"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+
+    Suggestion {
+        command,
+        explanation,
+    }
+}
+
+/// Returns why `command` is destructive, if it is.
+pub fn destructive_reason(command: &str) -> Option<&'static str> {
+    if command.contains("mkfs") {
+        return Some("formats a filesystem");
+    }
+    if command.contains(":(){") {
+        return Some("is a fork bomb");
+    }
+    if command.contains("> /dev/sd") || command.contains(">/dev/sd") {
+        return Some("writes raw data to a disk");
+    }
+
+    let reason = command.split(['|', ';', '&', '\n']).find_map(|segment| {
+        let words: Vec<&str> = segment.split_whitespace().collect();
+        invocations(&words).into_iter().find_map(invocation_reason)
+    });
+    reason.or_else(|| {
+        shell_scripts(command)
+            .iter()
+            .find_map(|script| destructive_reason(script))
+    })
+}
+
+/// The command lines passed to shells with `-c`, or combined like `-ec`, as
+/// in `sudo sh -c '...'`.
+fn shell_scripts(command: &str) -> Vec<String> {
+    let Ok(words) = shell_words::split(command) else {
+        return Vec::new();
+    };
+    let mut scripts = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        if !SHELLS.contains(&program(word)) {
+            continue;
+        }
+        let script = words[i + 1..]
+            .iter()
+            .take_while(|w| w.starts_with('-'))
+            .position(|w| !w.starts_with("--") && w.contains('c'))
+            .and_then(|j| words.get(i + j + 2));
+        scripts.extend(script.cloned());
+    }
+    scripts
+}
+
+/// The commands a segment runs: its program once wrappers like `sudo` or
+/// `xargs` are looked through, and those run by `find -exec`.
+fn invocations<'a>(words: &'a [&'a str]) -> Vec<&'a [&'a str]> {
+    let mut found = vec![unwrap(words)];
+    for (i, word) in words.iter().enumerate() {
+        if FIND_ACTIONS.contains(word) {
+            let rest = &words[i + 1..];
+            // `\;` ends the action too, but `;` already split the segment and
+            // left only its backslash.
+            let end = rest
+                .iter()
+                .position(|w| matches!(*w, "+" | "\\"))
+                .unwrap_or(rest.len());
+            found.push(unwrap(&rest[..end]));
+        }
+    }
+    found
+}
+
+/// Skips `NAME=value` assignments and wrappers with their options.
+fn unwrap<'a>(mut words: &'a [&'a str]) -> &'a [&'a str] {
+    loop {
+        while words.first().is_some_and(|w| is_assignment(w)) {
+            words = &words[1..];
+        }
+        let Some((_, options)) = words
+            .first()
+            .and_then(|first| WRAPPERS.iter().find(|(name, _)| *name == program(first)))
+        else {
+            return words;
+        };
+
+        words = &words[1..];
+        while let Some(word) = words.first() {
+            if *word == "--" {
+                words = &words[1..];
+                break;
+            }
+            if !word.starts_with('-') {
+                break;
+            }
+            let skip = if options.contains(word) { 2 } else { 1 };
+            words = &words[skip.min(words.len())..];
+        }
+    }
+}
+
+fn invocation_reason(words: &[&str]) -> Option<&'static str> {
+    let (first, args) = words.split_first()?;
+    match program(first) {
+        "rm" if has_flag(args, &['r', 'R'], &["--recursive"]) => Some("recursively deletes files"),
+        "find" if args.contains(&"-delete") => Some("deletes the files it finds"),
+        "dd" => Some("writes raw data to a device or file"),
+        "shred" => Some("irreversibly overwrites files"),
+        "wipefs" => Some("erases filesystem signatures"),
+        "chmod" if has_flag(args, &['R'], &["--recursive"]) => {
+            Some("recursively changes permissions")
+        }
+        "chown" if has_flag(args, &['R'], &["--recursive"]) => {
+            Some("recursively changes ownership")
+        }
+        "git" => git_reason(args),
+        _ => None,
+    }
+}
+
+fn git_reason(mut args: &[&str]) -> Option<&'static str> {
+    while let Some(arg) = args.first().filter(|arg| arg.starts_with('-')) {
+        let skip = if GIT_OPTIONS.contains(arg) { 2 } else { 1 };
+        args = &args[skip.min(args.len())..];
+    }
+    let (subcommand, args) = args.split_first()?;
+    match *subcommand {
+        "push"
+            if has_flag(
+                args,
+                &['f'],
+                &["--force", "--force-with-lease", "--force-if-includes"],
+            ) || args.iter().any(|arg| arg.starts_with('+')) =>
+        {
+            Some("rewrites remote history")
+        }
+        "reset" if args.contains(&"--hard") => Some("discards local changes"),
+        "clean" if has_flag(args, &['f'], &["--force"]) => Some("deletes untracked files"),
+        _ => None,
+    }
+}
+
+/// Whether `args` set one of the `short` flags, alone or combined like
+/// `-rfv`, or one of the `long` ones, possibly with `=value`.
+fn has_flag(args: &[&str], short: &[char], long: &[&str]) -> bool {
+    args.iter().take_while(|arg| **arg != "--").any(|arg| {
+        if arg.starts_with("--") {
+            long.contains(&arg.split('=').next().unwrap_or(arg))
+        } else {
+            arg.strip_prefix('-')
+                .is_some_and(|flags| flags.chars().any(|c| short.contains(&c)))
+        }
+    })
+}
+
+/// The program name without its directory, so `/bin/rm` is `rm`.
+fn program(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+fn read_line(input: &mut dyn BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// Implements `mergil sh`: asks for a command, shows it and lets the user
+/// execute, copy or revise it.
+pub async fn run(
+    request: &str,
+    model: &str,
+    provider: &dyn Provider,
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+    executor: &dyn CommandExecutor,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut chat = ChatRequest {
+        model: model.to_string(),
+        system: vec![system_prompt()],
+        contents: vec![request.to_string()],
+        ..ChatRequest::default()
+    };
+
+    loop {
+        let response =
+            complete_with_cancellation(provider, &chat, OutputFormat::Text, &mut io::sink())
+                .await?;
+        let suggestion = parse_suggestion(&response.content);
+
+        writeln!(out, "\n  {}\n", suggestion.command)?;
+        if !suggestion.explanation.is_empty() {
+            writeln!(out, "{}\n", suggestion.explanation)?;
+        }
+        let destructive = destructive_reason(&suggestion.command);
+        if let Some(reason) = destructive {
+            writeln!(out, "WARNING: this command {}.\n", reason)?;
+        }
+
+        write!(out, "[e]xecute / [c]opy / [r]evise / [q]uit: ")?;
+        out.flush()?;
+        match read_line(input)?.as_str() {
+            "e" | "execute" => {
+                if destructive.is_some() {
+                    write!(out, "Type 'yes' to run it anyway: ")?;
+                    out.flush()?;
+                    if read_line(input)? != "yes" {
+                        writeln!(out, "Not executed.")?;
+                        return Ok(());
+                    }
+                }
+                if let Some(code) = executor.execute(&suggestion.command)? {
+                    if code != 0 {
+                        warn!("Command exited with status {}", code);
+                    }
+                }
+                return Ok(());
+            }
+            "c" | "copy" => {
+                clipboard::write(&suggestion.command)?;
+                writeln!(out, "Copied to clipboard.")?;
+                return Ok(());
+            }
+            "r" | "revise" => {
+                write!(out, "What should change? ")?;
+                out.flush()?;
+                let revision = read_line(input)?;
+                chat.contents.push(format!(
+                    "You suggested: {}\nRevise it: {}",
+                    suggestion.command, revision
+                ));
+            }
+            _ => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::clipboard;
+use crate::config::Config;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A prompt template read from `<templates dir>/<name>.md`.
+///
+/// The file may start with a front-matter block of `key: value` lines between
+/// `---` markers setting `description`, `model`, `markdown` and `temperature`.
+/// The body supports `{{input}}`, `{{file:path}}`, `{{clipboard}}`,
+/// `{{shell:cmd}}` and `{{var}}` placeholders.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Template {
+    pub name: String,
+    pub description: Option<String>,
+    pub model: Option<String>,
+    pub markdown: Option<bool>,
+    pub temperature: Option<f64>,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    NotFound(String, PathBuf),
+    ReadFailed(PathBuf, io::Error),
+    InvalidFrontMatter(String, String),
+    MissingVariable(String),
+    PlaceholderFailed(String, String),
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::NotFound(name, dir) => {
+                write!(f, "Template '{}' not found in {}", name, dir.display())
+            }
+            TemplateError::ReadFailed(path, e) => {
+                write!(f, "Failed to read {}: {}", path.display(), e)
+            }
+            TemplateError::InvalidFrontMatter(name, e) => {
+                write!(f, "Invalid front-matter in template '{}': {}", name, e)
+            }
+            TemplateError::MissingVariable(name) => {
+                write!(
+                    f,
+                    "Template variable '{}' not set, pass --var {}=...",
+                    name, name
+                )
+            }
+            TemplateError::PlaceholderFailed(placeholder, e) => {
+                write!(f, "Failed to expand {{{{{}}}}}: {}", placeholder, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// The config's `templates_dir`, or `~/.config/mergil/templates`.
+pub fn templates_dir(config: &Config) -> Option<PathBuf> {
+    config
+        .templates_dir
+        .clone()
+        .or_else(|| dirs::config_dir().map(|dir| dir.join("mergil").join("templates")))
+}
+
+/// Lists every `*.md` template in `dir`, sorted by name. A missing directory
+/// has no templates.
+pub fn list(dir: &Path) -> Result<Vec<Template>, TemplateError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(TemplateError::ReadFailed(dir.to_path_buf(), e)),
+    };
+
+    let mut templates = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| TemplateError::ReadFailed(dir.to_path_buf(), e))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "md") {
+            templates.push(Template::from_path(&path)?);
+        }
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(templates)
+}
+
+impl Template {
+    pub fn load(dir: &Path, name: &str) -> Result<Template, TemplateError> {
+        let path = dir.join(format!("{}.md", name));
+        if !path.exists() {
+            return Err(TemplateError::NotFound(name.to_string(), dir.to_path_buf()));
+        }
+        Template::from_path(&path)
+    }
+
+    pub fn from_path(path: &Path) -> Result<Template, TemplateError> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let text =
+            fs::read_to_string(path).map_err(|e| TemplateError::ReadFailed(path.into(), e))?;
+        Template::parse(&name, &text)
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Template, TemplateError> {
+        let mut template = Template {
+            name: name.to_string(),
+            ..Template::default()
+        };
+
+        let Some(rest) = text.strip_prefix("---\n") else {
+            template.body = text.to_string();
+            return Ok(template);
+        };
+        let Some((front_matter, body)) = rest.split_once("\n---\n") else {
+            return Err(TemplateError::InvalidFrontMatter(
+                name.to_string(),
+                "missing closing ---".to_string(),
+            ));
+        };
+
+        let invalid =
+            |message: String| TemplateError::InvalidFrontMatter(name.to_string(), message);
+        for line in front_matter.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("expected `key: value`, got `{}`", line)))?;
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim() {
+                "description" => template.description = Some(value),
+                "model" => template.model = Some(value),
+                "markdown" => {
+                    template.markdown = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid("markdown must be true or false".to_string()))?,
+                    )
+                }
+                "temperature" => {
+                    template.temperature = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid("temperature must be a number".to_string()))?,
+                    )
+                }
+                key => return Err(invalid(format!("unknown key `{}`", key))),
+            }
+        }
+        template.body = body.to_string();
+
+        Ok(template)
+    }
+
+    /// Whether the body contains an `{{input}}` placeholder.
+    pub fn uses_input(&self) -> bool {
+        placeholder_regex()
+            .captures_iter(&self.body)
+            .any(|captures| &captures[1] == "input")
+    }
+
+    /// Expands every placeholder in the body.
+    pub fn render(
+        &self,
+        input: &str,
+        vars: &HashMap<String, String>,
+    ) -> Result<String, TemplateError> {
+        let mut rendered = String::with_capacity(self.body.len());
+        let mut last = 0;
+
+        for captures in placeholder_regex().captures_iter(&self.body) {
+            let whole = captures.get(0).unwrap();
+            rendered.push_str(&self.body[last..whole.start()]);
+            rendered.push_str(&expand(&captures[1], input, vars)?);
+            last = whole.end();
+        }
+        rendered.push_str(&self.body[last..]);
+
+        Ok(rendered)
+    }
+}
+
+fn placeholder_regex() -> Regex {
+    Regex::new(r"\{\{\s*(.+?)\s*\}\}").unwrap()
+}
+
+fn expand(
+    placeholder: &str,
+    input: &str,
+    vars: &HashMap<String, String>,
+) -> Result<String, TemplateError> {
+    let failed = |e: String| TemplateError::PlaceholderFailed(placeholder.to_string(), e);
+
+    if placeholder == "input" {
+        Ok(input.to_string())
+    } else if placeholder == "clipboard" {
+        clipboard::read().map_err(|e| failed(e.to_string()))
+    } else if let Some(path) = placeholder.strip_prefix("file:") {
+        fs::read_to_string(expand_home(path.trim())).map_err(|e| failed(e.to_string()))
+    } else if let Some(command) = placeholder.strip_prefix("shell:") {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command.trim())
+            .output()
+            .map_err(|e| failed(e.to_string()))?;
+        if !output.status.success() {
+            return Err(failed(format!(
+                "exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        vars.get(placeholder)
+            .cloned()
+            .ok_or_else(|| TemplateError::MissingVariable(placeholder.to_string()))
+    }
+}
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_home(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,136 @@
+use crate::markdown;
+use clap::ValueEnum;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use termimad::MadSkin;
+
+/// Pager used when `$PAGER` is not set.
+const DEFAULT_PAGER: &str = "less";
+
+/// When to style output with ANSI colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// When stdout is a terminal, following `NO_COLOR` and `CLICOLOR_FORCE`
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Whether to write colors. `always` and `never` win; with `auto` a
+/// non-empty `NO_COLOR` turns them off, then `CLICOLOR_FORCE` other than `0`
+/// turns them on, and otherwise they are used on a terminal.
+pub fn color_enabled(
+    choice: ColorChoice,
+    is_tty: bool,
+    var: impl Fn(&str) -> Option<String>,
+) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            if var("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+                false
+            } else if var("CLICOLOR_FORCE").is_some_and(|value| !value.is_empty() && value != "0") {
+                true
+            } else {
+                is_tty
+            }
+        }
+    }
+}
+
+/// The pager command from `$PAGER`, `less` when unset, or `None` when paging
+/// is turned off with an empty `PAGER` or `PAGER=cat`.
+pub fn pager_command(pager: Option<&str>) -> Option<Vec<String>> {
+    let words = shell_words::split(pager.unwrap_or(DEFAULT_PAGER)).ok()?;
+    match words.first().map(String::as_str) {
+        None | Some("cat") => None,
+        Some(_) => Some(words),
+    }
+}
+
+/// Renders Markdown for the terminal, styled with colors or as plain text,
+/// wrapped at `width` columns when given.
+pub fn render_markdown(text: &str, color: bool, width: Option<usize>) -> String {
+    let skin = if color {
+        markdown::create_madskin()
+    } else {
+        MadSkin::no_style()
+    };
+    skin.text(text, width).to_string()
+}
+
+/// How answers are printed on stdout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Display {
+    /// Render Markdown; when off it is written as it is
+    pub render: bool,
+    /// Style Markdown with colors
+    pub color: bool,
+    /// Wrap Markdown at this many columns; `None` does not wrap
+    pub width: Option<usize>,
+    /// Page output with at least this many lines
+    pub page_from: Option<usize>,
+    /// Pager command and arguments
+    pub pager: Option<Vec<String>>,
+}
+
+impl Display {
+    /// The display for stdout. On a terminal, Markdown is wrapped at its width
+    /// and answers taller than it are paged, unless `no_pager` is set. Files
+    /// and pipes get the Markdown source, unless colors are forced.
+    pub fn detect(color: ColorChoice, width: Option<usize>, no_pager: bool, is_tty: bool) -> Self {
+        let size = is_tty.then(termimad::terminal_size);
+        let color = color_enabled(color, is_tty, |name| std::env::var(name).ok());
+        Display {
+            render: is_tty || color,
+            color,
+            width: width.or(size.map(|(columns, _)| columns as usize)),
+            page_from: size.filter(|_| !no_pager).map(|(_, rows)| rows as usize),
+            pager: pager_command(std::env::var("PAGER").ok().as_deref()),
+        }
+    }
+
+    /// Writes `text`, rendered first when it is Markdown and `render` is set,
+    /// through the pager when it is too tall for the terminal.
+    pub fn print(&self, text: &str, markdown: bool, out: &mut dyn Write) -> io::Result<()> {
+        let text = if markdown && self.render {
+            render_markdown(text, self.color, self.width)
+        } else {
+            format!("{}\n", text)
+        };
+        match (&self.pager, self.page_from) {
+            (Some(pager), Some(rows)) if text.lines().count() >= rows => page(&text, pager, out),
+            _ => out.write_all(text.as_bytes()),
+        }
+    }
+}
+
+/// Shows `text` in `pager`, which writes to the inherited stdout. Falls back
+/// to `out` when the pager cannot be started. Like git, `LESS=FRX` is set
+/// unless `LESS` is, so colors pass through.
+fn page(text: &str, pager: &[String], out: &mut dyn Write) -> io::Result<()> {
+    let mut command = Command::new(&pager[0]);
+    command.args(&pager[1..]).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(_) => return out.write_all(text.as_bytes()),
+    };
+
+    let written = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(text.as_bytes());
+    match written {
+        // The pager was quit before reading everything.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result?,
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -1,5 +1,7 @@
-use mergil::api;
-use mergil::input::{self, EditorOpener, InputResult, StdinReader};
+use mergil::api::{self, ApiKeySource};
+use mergil::input::{self, EditorOpener, EditorStatus, InputResult, StdinReader};
+use mergil::prompts::SystemPrompts;
+use mergil::provider::{ChatRequest, OpenRouterProvider, Provider};
 use reqwest::Client;
 use std::cell::RefCell;
 
@@ -35,30 +37,33 @@ struct MockEditor {
 }
 
 impl EditorOpener for MockEditor {
-    fn open_editor(&self, temp_path: &str) -> std::io::Result<()> {
+    fn open_editor(&self, temp_path: &str) -> std::io::Result<EditorStatus> {
         std::fs::write(temp_path, self.content.borrow().as_bytes())?;
-        Ok(())
+        Ok(EditorStatus::Saved)
     }
 }
 
+/// Answers `contents` through the same provider the CLI uses.
+async fn complete(api_key: String, contents: Vec<String>, markdown: bool) -> String {
+    let provider = OpenRouterProvider::new(Client::new(), api_key, None);
+    let request = ChatRequest {
+        model: "deepseek/deepseek-coder".to_string(),
+        system: SystemPrompts::default().messages(markdown),
+        contents,
+        ..ChatRequest::default()
+    };
+    provider
+        .complete(&request, &mut |_: &str| {})
+        .await
+        .unwrap()
+        .content
+}
+
 #[tokio::test]
 async fn test_markdown_api_request() {
     if let Some(api_key) = setup_api_key().await {
         let test_data = "Generate a simple Rust function that adds two numbers.";
-        let contents_vec = vec![test_data.to_string()];
-
-        let client = Client::new();
-        let response = api::send_api_request(
-            &client,
-            &api_key,
-            "deepseek/deepseek-coder",
-            &contents_vec,
-            true,
-            None,
-            false,
-        )
-        .await
-        .unwrap();
+        let response = complete(api_key, vec![test_data.to_string()], true).await;
 
         assert!(!response.is_empty());
         assert!(response.contains("```"));
@@ -70,20 +75,8 @@ async fn test_multiple_inputs() {
     if let Some(api_key) = setup_api_key().await {
         let input1 = "What is a closure in Rust?";
         let input2 = "Provide an example of using a closure.";
-        let contents_vec = vec![input1.to_string(), input2.to_string()];
-
-        let client = Client::new();
-        let response = api::send_api_request(
-            &client,
-            &api_key,
-            "deepseek/deepseek-coder",
-            &contents_vec,
-            false,
-            None,
-            false,
-        )
-        .await
-        .unwrap();
+        let contents = vec![input1.to_string(), input2.to_string()];
+        let response = complete(api_key, contents, false).await;
 
         assert!(!response.is_empty());
     }
@@ -92,10 +85,8 @@ async fn test_multiple_inputs() {
 #[test]
 fn test_missing_api_key() {
     std::env::remove_var("OPENROUTER_API_KEY");
-    let result = std::panic::catch_unwind(|| {
-        api::get_api_key();
-    });
-    assert!(result.is_err());
+    let result = ApiKeySource::Env(api::DEFAULT_API_KEY_ENV.to_string()).resolve();
+    assert!(matches!(result, Err(api::ApiError::MissingApiKey(_))));
 }
 
 #[test]
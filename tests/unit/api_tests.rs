@@ -1,36 +1,18 @@
 use mergil::api::{self, ApiError};
+use mergil::image::Image;
 use std::env;
+use std::io::Write;
 use std::time::Duration;
 use tokio::time::timeout;
-use wiremock::matchers::{method, path};
+use wiremock::matchers::{body_partial_json, method, path};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
-pub async fn mock_successful_api_response(mock_server: &MockServer) {
-    Mock::given(method("POST"))
-        .and(path("/api/v1/chat/completions"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-            "id": "test-id",
-            "model": "test-model",
-            "object": "object-test",
-            "created": 10010,
-            "system_fingerprint": "none",
-            "usage": {
-                "prompt_tokens": 50,
-                "completion_tokens": 60,
-                "total_tokens": 110
-            },
-            "choices": [{
-                "finish_reason": "stop",
-                "index": 1,
-                "message": {
-                    "role": "assistant",
-                    "content": "Hello, world!"
-                }
-            }]
-        })))
-        .expect(1)
-        .mount(mock_server)
-        .await;
+fn request() -> api::ChatRequest {
+    api::ChatRequest {
+        model: "test-model".to_string(),
+        contents: vec!["Hello".to_string()],
+        ..Default::default()
+    }
 }
 
 pub async fn mock_error_api_response(mock_server: &MockServer) {
@@ -43,69 +25,352 @@ pub async fn mock_error_api_response(mock_server: &MockServer) {
 }
 
 #[tokio::test]
-async fn test_send_api_request_success() {
+async fn test_stream_api_request_error() {
     let mock_server = MockServer::start().await;
-
-    mock_successful_api_response(&mock_server).await;
+    mock_error_api_response(&mock_server).await;
 
     env::set_var("OPENROUTER_API_KEY", "test_key");
-    let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
     let client = reqwest::Client::new();
+    let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
     let result = timeout(
         Duration::from_secs(5),
-        api::send_api_request(
+        api::stream_api_request(
             &client,
             "test_key",
-            "test-model",
-            &vec!["Hello".to_string()],
-            false,
+            &request(),
             Some(&url),
-            false,
+            &mut |_: &str| {},
         ),
     )
     .await;
 
     assert!(result.is_ok());
-    assert_eq!(result.unwrap().unwrap(), "Hello, world!");
+    let error = result.unwrap().unwrap_err();
+    assert!(matches!(error, ApiError::ApiErrorResponse(_)));
+}
+
+#[test]
+fn test_api_error_display() {
+    let parse_error = ApiError::ResponseParseFailed(
+        serde_json::from_str::<serde_json::Value>("invalid json").unwrap_err(),
+    );
+    assert!(format!("{}", parse_error).contains("Failed to parse response"));
+
+    let api_error = ApiError::ApiErrorResponse("Bad request".to_string());
+    assert_eq!(format!("{}", api_error), "API error: Bad request");
+
+    let retry_error = ApiError::RetryExhausted;
+    assert_eq!(format!("{}", retry_error), "Retry attempts exhausted");
+
+    assert_eq!(format!("{}", ApiError::Cancelled), "Request cancelled");
 }
 
 #[tokio::test]
-async fn test_send_api_request_error() {
+async fn test_stream_api_request_collects_deltas() {
     let mock_server = MockServer::start().await;
-    mock_error_api_response(&mock_server).await;
+    let body = concat!(
+        ": OPENROUTER PROCESSING\n\n",
+        "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\", world!\"}}]}\n\n",
+        "data: {\"model\":\"test-model-v2\",\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":3,\"total_tokens\":8}}\n\n",
+        "data: [DONE]\n\n",
+    );
+    Mock::given(method("POST"))
+        .and(path("/api/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
 
-    env::set_var("OPENROUTER_API_KEY", "test_key");
+    let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
     let client = reqwest::Client::new();
+    let mut deltas = Vec::new();
+    let mut on_delta = |delta: &str| deltas.push(delta.to_string());
+    let request = api::ChatRequest {
+        model: "test-model".to_string(),
+        contents: vec!["Hello".to_string()],
+        ..Default::default()
+    };
+    let result =
+        api::stream_api_request(&client, "test_key", &request, Some(&url), &mut on_delta).await;
+
+    let completion = result.unwrap();
+    assert_eq!(completion.content, "Hello, world!");
+    assert_eq!(completion.model.as_deref(), Some("test-model-v2"));
+    assert_eq!(completion.finish_reason.as_deref(), Some("stop"));
+    assert_eq!(completion.usage.unwrap().total_tokens, 8);
+    assert_eq!(deltas, vec!["Hello", ", world!"]);
+}
+
+#[tokio::test]
+async fn test_stream_api_request_error_in_stream() {
+    let mock_server = MockServer::start().await;
+    let body = concat!(
+        ": OPENROUTER PROCESSING\n\n",
+        "data: {\"error\":{\"code\":429,\"message\":\"Provider is overloaded\"}}\n\n",
+        "data: [DONE]\n\n",
+    );
+    Mock::given(method("POST"))
+        .and(path("/api/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+        .mount(&mock_server)
+        .await;
+
+    let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
+    let request = api::ChatRequest {
+        model: "test-model".to_string(),
+        contents: vec!["Hello".to_string()],
+        ..Default::default()
+    };
+    let error = api::stream_api_request(
+        &reqwest::Client::new(),
+        "test_key",
+        &request,
+        Some(&url),
+        &mut |_: &str| {},
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(error.code(), "rate_limited");
+    assert_eq!(
+        error.to_string(),
+        "Rate limited: Provider is overloaded (code 429)"
+    );
+}
+
+#[tokio::test]
+async fn test_stream_api_request_error_after_deltas() {
+    let mock_server = MockServer::start().await;
+    let body = concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Partial\"}}]}\n\n",
+        "data: {\"error\":{\"code\":502,\"message\":\"Upstream died\"}}\n\n",
+    );
+    Mock::given(method("POST"))
+        .and(path("/api/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
+    let request = api::ChatRequest {
+        model: "test-model".to_string(),
+        contents: vec!["Hello".to_string()],
+        ..Default::default()
+    };
+    let mut deltas = Vec::new();
+    let error = api::stream_api_request(
+        &reqwest::Client::new(),
+        "test_key",
+        &request,
+        Some(&url),
+        &mut |delta: &str| deltas.push(delta.to_string()),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(error.code(), "model_unavailable");
+    assert_eq!(deltas, vec!["Partial"]);
+}
+
+#[tokio::test]
+async fn test_stream_api_request_sends_response_format() {
+    let mock_server = MockServer::start().await;
+    let response_format = serde_json::json!({
+        "type": "json_schema",
+        "json_schema": { "name": "labels", "schema": { "type": "object" } }
+    });
+    Mock::given(method("POST"))
+        .and(path("/api/v1/chat/completions"))
+        .and(body_partial_json(
+            serde_json::json!({ "response_format": response_format }),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"{}\"}}]}\n\ndata: [DONE]\n\n",
+            "text/event-stream",
+        ))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
+    let request = api::ChatRequest {
+        model: "test-model".to_string(),
+        contents: vec!["Hello".to_string()],
+        response_format: Some(response_format),
+        ..Default::default()
+    };
+    let completion = api::stream_api_request(
+        &reqwest::Client::new(),
+        "test_key",
+        &request,
+        Some(&url),
+        &mut |_: &str| {},
+    )
+    .await
+    .unwrap();
+    assert_eq!(completion.content, "{}");
+}
+
+#[tokio::test]
+async fn test_build_client_times_out() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .mount(&mock_server)
+        .await;
+
     let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
+    let client = api::build_client(Duration::from_secs(1), Duration::from_millis(100)).unwrap();
     let result = timeout(
         Duration::from_secs(5),
-        api::send_api_request(
+        api::stream_api_request(
             &client,
             "test_key",
-            "test-model",
-            &vec!["Hello".to_string()],
-            false,
+            &request(),
             Some(&url),
-            false,
+            &mut |_: &str| {},
         ),
     )
-    .await;
+    .await
+    .unwrap();
 
-    assert!(result.is_ok());
-    let error = result.unwrap().unwrap_err();
-    assert!(matches!(error, ApiError::ApiErrorResponse(_)));
+    assert!(matches!(result, Err(ApiError::RequestFailed(e)) if e.is_timeout()));
 }
 
 #[test]
-fn test_api_error_display() {
-    let parse_error = ApiError::ResponseParseFailed(
-        serde_json::from_str::<serde_json::Value>("invalid json").unwrap_err(),
+fn test_api_key_source_env() {
+    env::set_var("MERGIL_TEST_API_KEY", "  env_key\n");
+    let source = api::ApiKeySource::Env("MERGIL_TEST_API_KEY".to_string());
+    assert_eq!(source.resolve().unwrap(), "env_key");
+
+    let missing = api::ApiKeySource::Env("MERGIL_TEST_MISSING_KEY".to_string());
+    let error = missing.resolve().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Missing API key: environment variable MERGIL_TEST_MISSING_KEY is not set"
     );
-    assert!(format!("{}", parse_error).contains("Failed to parse response"));
+}
 
-    let api_error = ApiError::ApiErrorResponse("Bad request".to_string());
-    assert_eq!(format!("{}", api_error), "API error: Bad request");
+#[test]
+fn test_api_key_source_file() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "file_key").unwrap();
+    let source = api::ApiKeySource::File(file.path().to_path_buf());
+    assert_eq!(source.resolve().unwrap(), "file_key");
 
-    let retry_error = ApiError::RetryExhausted;
-    assert_eq!(format!("{}", retry_error), "Retry attempts exhausted");
+    let empty = tempfile::NamedTempFile::new().unwrap();
+    let source = api::ApiKeySource::File(empty.path().to_path_buf());
+    assert!(matches!(source.resolve(), Err(ApiError::MissingApiKey(_))));
+}
+
+#[test]
+fn test_api_key_source_command() {
+    let source = api::ApiKeySource::Command("echo command_key".to_string());
+    assert_eq!(source.resolve().unwrap(), "command_key");
+
+    let failing = api::ApiKeySource::Command("echo nope >&2; exit 1".to_string());
+    let error = failing.resolve().unwrap_err();
+    assert!(matches!(error, ApiError::MissingApiKey(_)));
+    assert!(error.to_string().contains("nope"));
+}
+
+#[test]
+fn test_build_messages() {
+    let history = [
+        api::Turn {
+            role: api::Role::User,
+            content: "Hi".to_string(),
+        },
+        api::Turn {
+            role: api::Role::Assistant,
+            content: "Hi there".to_string(),
+        },
+    ];
+    let messages = api::build_messages(
+        &["Be brief".to_string()],
+        &history,
+        &["Hello".to_string(), "World".to_string()],
+    );
+    assert_eq!(
+        messages,
+        vec![
+            serde_json::json!({"role": "system", "content": "Be brief"}),
+            serde_json::json!({"role": "user", "content": "Hi"}),
+            serde_json::json!({"role": "assistant", "content": "Hi there"}),
+            serde_json::json!({"role": "user", "content": "Hello"}),
+            serde_json::json!({"role": "user", "content": "World"}),
+        ]
+    );
+}
+
+#[test]
+fn test_attach_images() {
+    let image = Image {
+        path: "shot.png".into(),
+        mime: "image/png".to_string(),
+        data: "AAAA".to_string(),
+    };
+    let mut messages =
+        api::build_messages(&["System".to_string()], &[], &["What is this?".to_string()]);
+    api::attach_images(&mut messages, std::slice::from_ref(&image));
+
+    assert_eq!(messages[0]["content"], "System");
+    assert_eq!(
+        messages[1],
+        serde_json::json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": "What is this?" },
+                { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } }
+            ]
+        })
+    );
+
+    let mut messages = api::build_messages(&["System".to_string()], &[], &[]);
+    api::attach_images(&mut messages, &[image]);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1]["content"][0]["type"], "image_url");
+}
+
+#[tokio::test]
+async fn test_stream_api_request_classifies_status() {
+    let cases = [
+        (401, "unauthorized"),
+        (429, "rate_limited"),
+        (503, "model_unavailable"),
+        (500, "api_error"),
+    ];
+
+    for (status, code) in cases {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(status).set_body_json(serde_json::json!({
+                    "error": { "message": "Something went wrong", "code": status }
+                })),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let url = format!("{}/api/v1/chat/completions", &mock_server.uri());
+        let error = api::stream_api_request(
+            &reqwest::Client::new(),
+            "test_key",
+            &request(),
+            Some(&url),
+            &mut |_: &str| {},
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(error.code(), code);
+        assert!(error
+            .to_string()
+            .ends_with(&format!("Something went wrong (code {})", status)));
+    }
 }
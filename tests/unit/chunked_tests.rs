@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use mergil::api::{ApiError, Completion};
+use mergil::chunked::{map_chunks, ChunkOptions};
+use mergil::provider::{ChatRequest, MockProvider, Provider};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn paragraphs(count: usize) -> String {
+    (1..=count)
+        .map(|i| format!("Paragraph {} talks about topic {} at some length.", i, i))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn options(parallelism: usize) -> ChunkOptions {
+    ChunkOptions {
+        model: "cheap/model".to_string(),
+        chunk_tokens: 30,
+        parallelism,
+    }
+}
+
+#[tokio::test]
+async fn test_map_chunks_replaces_largest_entry_with_notes() {
+    let provider = MockProvider::new()
+        .with_response("notes 1")
+        .with_response("notes 2")
+        .with_response("notes 3");
+    let contents = vec!["What topics are covered?".to_string(), paragraphs(6)];
+    let mut progress = Vec::new();
+
+    let mapped = map_chunks(&provider, &contents, &options(1), &mut progress)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(mapped[0], "What topics are covered?");
+    assert_eq!(
+        mapped[1],
+        "## Notes on part 1 of 3\n\nnotes 1\n\n## Notes on part 2 of 3\n\nnotes 2\n\n## Notes on part 3 of 3\n\nnotes 3"
+    );
+
+    let requests = provider.requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests.iter().all(|r| r.model == "cheap/model"));
+    assert!(requests[0].system[0].contains("part 1 of 3"));
+    assert!(requests[0].system[0].contains("What topics are covered?"));
+    assert!(requests[0].contents[0].starts_with("Paragraph 1 "));
+    assert!(requests[2].contents[0].ends_with("topic 6 at some length."));
+
+    let progress = String::from_utf8(progress).unwrap();
+    assert!(progress.contains("Split the input into 3 chunks"));
+    assert!(progress.contains("Processed chunk 3/3"));
+}
+
+#[tokio::test]
+async fn test_map_chunks_skips_small_input() {
+    let provider = MockProvider::new();
+    let contents = vec!["Short input".to_string()];
+
+    let mapped = map_chunks(&provider, &contents, &options(2), &mut Vec::new())
+        .await
+        .unwrap();
+
+    assert!(mapped.is_none());
+    assert!(provider.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_map_chunks_propagates_errors() {
+    let provider = MockProvider::new()
+        .with_response("notes 1")
+        .with_error(ApiError::RateLimited("slow down".to_string()));
+    let contents = vec![paragraphs(4)];
+
+    let result = map_chunks(&provider, &contents, &options(1), &mut Vec::new()).await;
+
+    assert!(matches!(result, Err(ApiError::RateLimited(_))));
+}
+
+/// Records how many requests run at the same time.
+#[derive(Default)]
+struct CountingProvider {
+    running: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+#[async_trait]
+impl Provider for CountingProvider {
+    async fn complete(
+        &self,
+        request: &ChatRequest,
+        _on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<Completion, ApiError> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        Ok(Completion {
+            content: format!("{} chars", request.contents[0].len()),
+            ..Completion::default()
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_map_chunks_bounds_parallelism() {
+    let provider = CountingProvider::default();
+    let contents = vec![paragraphs(20)];
+
+    let mapped = map_chunks(&provider, &contents, &options(3), &mut Vec::new())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(mapped[0].contains("## Notes on part 10 of 10"));
+    assert_eq!(provider.peak.load(Ordering::SeqCst), 3);
+}
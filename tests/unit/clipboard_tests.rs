@@ -0,0 +1,109 @@
+use mergil::clipboard::{write_osc52, Clipboard};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Installs an executable shell script named `name` in `dir`.
+fn stub(dir: &Path, name: &str, script: &str) {
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_write_uses_copy_command() {
+    let dir = TempDir::new().unwrap();
+    let copied = dir.path().join("copied");
+    stub(
+        dir.path(),
+        "xclip",
+        &format!("/bin/cat > {}", copied.display()),
+    );
+
+    let clipboard = Clipboard::from_env()
+        .with_path(dir.path())
+        .with_remote(false);
+    let mut terminal = Vec::new();
+    clipboard.write("copied text", &mut terminal).unwrap();
+
+    assert_eq!(fs::read_to_string(copied).unwrap(), "copied text");
+    assert!(terminal.is_empty());
+}
+
+#[test]
+fn test_write_skips_failing_commands() {
+    let dir = TempDir::new().unwrap();
+    let copied = dir.path().join("copied");
+    stub(dir.path(), "wl-copy", "exit 1");
+    stub(
+        dir.path(),
+        "xsel",
+        &format!("/bin/cat > {}", copied.display()),
+    );
+
+    let clipboard = Clipboard::from_env()
+        .with_path(dir.path())
+        .with_remote(false);
+    clipboard.write("fallback", &mut Vec::new()).unwrap();
+
+    assert_eq!(fs::read_to_string(copied).unwrap(), "fallback");
+}
+
+#[test]
+fn test_write_falls_back_to_osc52() {
+    let dir = TempDir::new().unwrap();
+    let clipboard = Clipboard::from_env()
+        .with_path(dir.path())
+        .with_remote(false);
+    let mut terminal = Vec::new();
+    clipboard.write("hi", &mut terminal).unwrap();
+
+    assert!(String::from_utf8(terminal)
+        .unwrap()
+        .contains("\x1b]52;c;aGk=\x07"));
+}
+
+#[test]
+fn test_write_uses_osc52_in_remote_session() {
+    let dir = TempDir::new().unwrap();
+    let copied = dir.path().join("copied");
+    stub(
+        dir.path(),
+        "wl-copy",
+        &format!("/bin/cat > {}", copied.display()),
+    );
+
+    let clipboard = Clipboard::from_env()
+        .with_path(dir.path())
+        .with_remote(true);
+    let mut terminal = Vec::new();
+    clipboard.write("hi", &mut terminal).unwrap();
+
+    assert!(!copied.exists());
+    assert!(!terminal.is_empty());
+}
+
+#[test]
+fn test_read_uses_paste_command() {
+    let dir = TempDir::new().unwrap();
+    stub(dir.path(), "wl-paste", "printf 'from the clipboard'");
+
+    let clipboard = Clipboard::from_env().with_path(dir.path());
+    assert_eq!(clipboard.read().unwrap(), "from the clipboard");
+}
+
+#[test]
+fn test_read_without_tools_fails() {
+    let dir = TempDir::new().unwrap();
+    let clipboard = Clipboard::from_env().with_path(dir.path());
+    assert!(clipboard.read().is_err());
+}
+
+#[test]
+fn test_write_osc52() {
+    let mut terminal = Vec::new();
+    write_osc52("copy me", &mut terminal).unwrap();
+    let sequence = String::from_utf8(terminal).unwrap();
+    assert!(sequence.contains("\x1b]52;c;Y29weSBtZQ==\x07"));
+}
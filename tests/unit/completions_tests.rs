@@ -0,0 +1,98 @@
+use clap::Parser;
+use clap_complete::Shell;
+use mergil::common::{Cli, Command};
+use mergil::completions;
+use mergil::config::Config;
+use mergil::models::{Catalog, Model};
+use std::collections::HashMap;
+
+fn catalog(ids: &[&str]) -> Catalog {
+    Catalog {
+        source: "https://openrouter.ai/api/v1".to_string(),
+        fetched: 0,
+        models: ids
+            .iter()
+            .map(|id| Model {
+                id: id.to_string(),
+                context_length: None,
+                prompt_price: None,
+                completion_price: None,
+                modality: None,
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn test_model_names_lists_aliases_then_catalog() {
+    let config = Config {
+        aliases: HashMap::from([
+            ("smart".to_string(), "openai/gpt-4o".to_string()),
+            ("fast".to_string(), "openai/gpt-4o-mini".to_string()),
+        ]),
+        ..Config::default()
+    };
+
+    let names = completions::model_names(&config, Some(&catalog(&["openai/gpt-4o"])));
+
+    assert_eq!(names, vec!["fast", "smart", "openai/gpt-4o"]);
+    assert_eq!(
+        completions::model_names(&Config::default(), None),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn test_complete_chain_completes_the_last_model() {
+    let names = vec![
+        "fast".to_string(),
+        "openai/gpt-4o".to_string(),
+        "openai/gpt-4o-mini".to_string(),
+    ];
+
+    assert_eq!(
+        completions::complete_chain("openai/", &names),
+        vec!["openai/gpt-4o", "openai/gpt-4o-mini"]
+    );
+    assert_eq!(
+        completions::complete_chain("openai/gpt-4o,f", &names),
+        vec!["openai/gpt-4o,fast"]
+    );
+    assert!(completions::complete_chain("x", &names).is_empty());
+}
+
+#[test]
+fn test_write_registration() {
+    for (shell, expected) in [
+        (Shell::Bash, "COMPLETE=\"bash\""),
+        (Shell::Zsh, "COMPLETE=\"zsh\""),
+        (Shell::Fish, "COMPLETE=fish"),
+    ] {
+        let mut out = Vec::new();
+        completions::write_registration(shell, &mut out).unwrap();
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("mergil"), "{}", script);
+        assert!(script.contains(expected), "{}", script);
+    }
+}
+
+#[test]
+fn test_write_man_page() {
+    let mut out = Vec::new();
+    completions::write_man_page(&mut out).unwrap();
+    let page = String::from_utf8(out).unwrap();
+    assert!(page.contains(".TH mergil 1"));
+    assert!(page.contains("\\-\\-model"));
+}
+
+#[test]
+fn test_parse_completions_command() {
+    let cli = Cli::parse_from(["mergil", "completions", "powershell"]);
+    assert!(matches!(
+        cli.command,
+        Some(Command::Completions {
+            shell: Shell::PowerShell
+        })
+    ));
+    assert!(Cli::try_parse_from(["mergil", "completions", "tcsh"]).is_err());
+}
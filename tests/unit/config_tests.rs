@@ -0,0 +1,143 @@
+use mergil::config::{Config, ConfigError, ModelSpec, StepInput, StepOutput};
+use std::io::Write;
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_config_from_path() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "connect_timeout = 5\ntimeout = 60").unwrap();
+
+    let config = Config::from_path(file.path().to_path_buf()).unwrap();
+    assert_eq!(config.connect_timeout, Some(5));
+    assert_eq!(config.timeout, Some(60));
+}
+
+#[test]
+fn test_config_defaults_when_empty() {
+    let file = NamedTempFile::new().unwrap();
+
+    let config = Config::from_path(file.path().to_path_buf()).unwrap();
+    assert_eq!(config.connect_timeout, None);
+    assert_eq!(config.timeout, None);
+}
+
+#[test]
+fn test_config_rejects_unknown_keys() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "timeuot = 60").unwrap();
+
+    let result = Config::from_path(file.path().to_path_buf());
+    assert!(matches!(result, Err(ConfigError::ParseFailed(_, _))));
+}
+
+#[test]
+fn test_config_editor_comment_prefix() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "[editor]\ncomment_prefix = \";\"").unwrap();
+
+    let config = Config::from_path(file.path().to_path_buf()).unwrap();
+    assert_eq!(config.editor.comment_prefix.as_deref(), Some(";"));
+}
+
+#[test]
+fn test_config_pipelines() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(
+        file,
+        r#"
+[pipelines.review]
+description = "Review a diff"
+
+[[pipelines.review.steps]]
+name = "bugs"
+model = "openai/gpt-4o"
+system = "Find bugs"
+input = "original"
+
+[[pipelines.review.steps]]
+after = ["bugs"]
+input = "both"
+output = "code"
+"#
+    )
+    .unwrap();
+
+    let config = Config::from_path(file.path().to_path_buf()).unwrap();
+    let review = &config.pipelines["review"];
+    assert_eq!(review.description.as_deref(), Some("Review a diff"));
+    assert_eq!(review.steps.len(), 2);
+    assert_eq!(review.steps[0].name.as_deref(), Some("bugs"));
+    assert_eq!(review.steps[0].input, StepInput::Original);
+    assert_eq!(review.steps[0].output, StepOutput::Text);
+    assert_eq!(review.steps[1].after, Some(vec!["bugs".to_string()]));
+    assert_eq!(review.steps[1].input, StepInput::Both);
+    assert_eq!(review.steps[1].output, StepOutput::Code);
+}
+
+#[test]
+fn test_config_model_and_aliases() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(
+        file,
+        "model = [\"sonnet\", \"openai/gpt-4o\"]\n[aliases]\nsonnet = \"anthropic/claude-3.5-sonnet\""
+    )
+    .unwrap();
+
+    let config = Config::from_path(file.path().to_path_buf()).unwrap();
+    assert_eq!(
+        config.model,
+        Some(ModelSpec::Chain(vec![
+            "sonnet".to_string(),
+            "openai/gpt-4o".to_string()
+        ]))
+    );
+    assert_eq!(
+        config.resolve_alias("sonnet"),
+        "anthropic/claude-3.5-sonnet"
+    );
+    assert_eq!(config.resolve_alias("openai/gpt-4o"), "openai/gpt-4o");
+
+    let single: Config = toml::from_str("model = \"sonnet\"").unwrap();
+    assert_eq!(single.model.unwrap().models(), vec!["sonnet"]);
+}
+
+#[test]
+fn test_config_expands_home_in_paths() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(
+        file,
+        "api_key_file = \"~/.secrets/openrouter\"\ntemplates_dir = \"/etc/mergil\""
+    )
+    .unwrap();
+
+    let config = Config::from_path(file.path().to_path_buf()).unwrap();
+    let home = dirs::home_dir().unwrap();
+    assert_eq!(
+        config.api_key_file,
+        Some(home.join(".secrets").join("openrouter"))
+    );
+    assert_eq!(config.templates_dir, Some("/etc/mergil".into()));
+}
+
+#[test]
+fn test_config_providers() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(
+        file,
+        "model = [\"sonnet\", \"local:qwen2.5\"]\n\
+         [providers.local]\n\
+         base_url = \"http://localhost:11434/v1\""
+    )
+    .unwrap();
+
+    let config = Config::from_path(file.path().to_path_buf()).unwrap();
+    let local = &config.providers["local"];
+    assert_eq!(local.base_url, "http://localhost:11434/v1");
+    assert_eq!(local.api_key_env, None);
+    assert_eq!(config.route("local:qwen2.5"), Some(("local", "qwen2.5")));
+    assert_eq!(config.route("qwen2.5:7b"), None);
+    assert_eq!(config.route("openai/gpt-4o"), None);
+
+    let missing: Result<Config, _> = toml::from_str("[providers.local]\napi_key_env = \"KEY\"");
+    assert!(missing.is_err());
+}
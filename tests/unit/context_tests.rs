@@ -0,0 +1,160 @@
+use mergil::context::{estimate_contents, fit, input_budget, model_info, Family, Overflow};
+
+fn log(lines: usize) -> String {
+    (1..=lines)
+        .map(|i| format!("2024-07-01 12:00:{:02} INFO line {}", i % 60, i))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_model_info() {
+    let info = model_info("anthropic/claude-3.5-sonnet").unwrap();
+    assert_eq!(info.family, Family::Claude);
+    assert_eq!(info.context_window, 200_000);
+
+    assert_eq!(model_info("openai/gpt-4").unwrap().context_window, 8_192);
+    assert_eq!(
+        model_info("openai/gpt-4o-mini").unwrap().context_window,
+        128_000
+    );
+    assert_eq!(
+        model_info("meta-llama/llama-3.1-8b-instruct:free")
+            .unwrap()
+            .context_window,
+        131_072
+    );
+    assert!(model_info("local/unknown").is_none());
+}
+
+#[test]
+fn test_model_info_needs_exact_id() {
+    assert!(model_info("meta-llama/llama-3.3-70b-instruct").is_none());
+    assert!(model_info("openai/gpt-4.1").is_none());
+    assert!(model_info("mistralai/mistral-nemo").is_none());
+    assert_eq!(
+        model_info("meta-llama/llama-3-8b-instruct")
+            .unwrap()
+            .context_window,
+        8_192
+    );
+}
+
+#[test]
+fn test_family_of_unknown_models() {
+    assert_eq!(Family::of("ollama/llama3"), Family::Llama);
+    assert_eq!(Family::of("azure/gpt-35"), Family::Gpt);
+    assert_eq!(Family::of("some/model"), Family::Other);
+}
+
+#[test]
+fn test_estimate_tokens() {
+    assert_eq!(Family::Gpt.estimate_tokens(""), 0);
+    assert_eq!(Family::Gpt.estimate_tokens("abcdefgh"), 2);
+    assert_eq!(Family::Claude.estimate_tokens("abcdefg"), 2);
+    assert_eq!(Family::Gpt.estimate_tokens("日本語"), 3);
+}
+
+#[test]
+fn test_input_budget_reserves_output_and_system() {
+    assert_eq!(input_budget(Family::Gpt, 100_000, &[]), 100_000 - 4096);
+    assert_eq!(input_budget(Family::Gpt, 8_000, &[]), 6_000);
+    assert_eq!(
+        input_budget(Family::Gpt, 8_000, &["abcdefgh".to_string()]),
+        6_000 - 6
+    );
+}
+
+#[test]
+fn test_fit_leaves_small_input_alone() {
+    let mut contents = vec!["short".to_string()];
+    assert!(fit(&mut contents, Family::Gpt, 100, Overflow::TruncateHead).is_empty());
+    assert_eq!(contents, vec!["short"]);
+}
+
+#[test]
+fn test_fit_truncate_head_keeps_the_end() {
+    let mut contents = vec!["Why does this fail?".to_string(), log(500)];
+    let cuts = fit(&mut contents, Family::Gpt, 1_000, Overflow::TruncateHead);
+
+    assert_eq!(cuts.len(), 1);
+    assert_eq!(cuts[0].index, 1);
+    assert!(cuts[0]
+        .removed
+        .starts_with("2024-07-01 12:00:01 INFO line 1\n"));
+    assert_eq!(contents[0], "Why does this fail?");
+    assert!(contents[1].starts_with(&format!(
+        "[... {} lines cut to fit the context window ...]\n",
+        cuts[0].lines
+    )));
+    assert!(contents[1].ends_with("INFO line 500"));
+    assert!(estimate_contents(Family::Gpt, &contents) <= 1_000);
+}
+
+#[test]
+fn test_fit_truncate_middle_keeps_both_ends() {
+    let mut contents = vec![log(500)];
+    let cuts = fit(&mut contents, Family::Gpt, 1_000, Overflow::TruncateMiddle);
+
+    assert_eq!(cuts.len(), 1);
+    assert!(contents[0].starts_with("2024-07-01 12:00:01 INFO line 1\n"));
+    assert!(contents[0].contains(" lines cut to fit the context window ...]"));
+    assert!(contents[0].ends_with("INFO line 500"));
+    assert_eq!(contents[0].lines().count(), 500 - cuts[0].lines + 1);
+    assert!(estimate_contents(Family::Gpt, &contents) <= 1_000);
+}
+
+#[test]
+fn test_fit_truncate_head_cuts_inside_a_long_line() {
+    let line = format!("{}END", "abcdefgh".repeat(2_000));
+    let mut contents = vec![line.clone()];
+    let cuts = fit(&mut contents, Family::Gpt, 1_000, Overflow::TruncateHead);
+
+    assert_eq!(cuts.len(), 1);
+    assert_eq!(cuts[0].lines, 1);
+    assert!(contents[0].starts_with("[... 1 lines cut to fit the context window ...]\n"));
+    assert!(contents[0].ends_with("abcdefghEND"));
+    assert!(contents[0].len() > 1_000);
+    assert_eq!(
+        format!(
+            "{}{}",
+            cuts[0].removed,
+            contents[0].split_once('\n').unwrap().1
+        ),
+        line
+    );
+    assert!(estimate_contents(Family::Gpt, &contents) <= 1_000);
+}
+
+#[test]
+fn test_fit_truncate_middle_cuts_inside_a_long_line() {
+    let mut contents = vec![format!("START{}", "日本語".repeat(5_000))];
+    let cuts = fit(&mut contents, Family::Gpt, 1_000, Overflow::TruncateMiddle);
+
+    assert_eq!(cuts.len(), 1);
+    assert!(contents[0].starts_with("START日本語"));
+    assert!(contents[0].contains("\n[... 1 lines cut to fit the context window ...]\n"));
+    assert!(contents[0].ends_with("日本語"));
+    assert!(estimate_contents(Family::Gpt, &contents) <= 1_000);
+}
+
+#[test]
+fn test_chunk_splits_at_paragraphs() {
+    let text = "aaaa aaaa aaaa\n\nbbbb bbbb bbbb\n\ncccc cccc cccc";
+    let chunks = mergil::context::chunk(text, Family::Gpt, 10);
+    assert_eq!(
+        chunks,
+        vec!["aaaa aaaa aaaa\n\nbbbb bbbb bbbb", "cccc cccc cccc"]
+    );
+}
+
+#[test]
+fn test_chunk_splits_long_paragraphs_at_lines() {
+    let text = log(100);
+    let chunks = mergil::context::chunk(&text, Family::Gpt, 200);
+    assert!(chunks.len() > 1);
+    assert!(chunks
+        .iter()
+        .all(|chunk| Family::Gpt.estimate_tokens(chunk) <= 200));
+    assert_eq!(chunks.join("\n"), text);
+}
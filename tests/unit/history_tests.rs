@@ -0,0 +1,78 @@
+use mergil::history::{format_time, Entry, History, HistoryError, RunOptions};
+use tempfile::TempDir;
+
+fn entry(prompt: &str) -> Entry {
+    Entry {
+        id: 0,
+        time: 1_700_000_000,
+        model: "test/model".to_string(),
+        cwd: "/tmp".into(),
+        session: "abc".to_string(),
+        prompt: vec![prompt.to_string()],
+        answer: format!("Answer to {}", prompt),
+        options: RunOptions::default(),
+    }
+}
+
+#[test]
+fn test_missing_history_is_empty() {
+    let dir = TempDir::new().unwrap();
+    let history = History::new(dir.path().join("none.jsonl"), 10);
+    assert!(history.entries().unwrap().is_empty());
+}
+
+#[test]
+fn test_record_assigns_ids_and_creates_dirs() {
+    let dir = TempDir::new().unwrap();
+    let history = History::new(dir.path().join("nested").join("history.jsonl"), 10);
+
+    assert_eq!(history.record(entry("first")).unwrap(), 1);
+    assert_eq!(history.record(entry("second")).unwrap(), 2);
+
+    let entries = history.entries().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].prompt, vec!["second"]);
+    assert_eq!(history.get(1).unwrap().answer, "Answer to first");
+}
+
+#[test]
+fn test_record_enforces_retention() {
+    let dir = TempDir::new().unwrap();
+    let history = History::new(dir.path().join("history.jsonl"), 2);
+
+    for prompt in ["one", "two", "three"] {
+        history.record(entry(prompt)).unwrap();
+    }
+
+    let ids: Vec<u64> = history.entries().unwrap().iter().map(|e| e.id).collect();
+    assert_eq!(ids, vec![2, 3]);
+    assert_eq!(history.record(entry("four")).unwrap(), 4);
+}
+
+#[test]
+fn test_get_missing_entry() {
+    let dir = TempDir::new().unwrap();
+    let history = History::new(dir.path().join("history.jsonl"), 10);
+    assert!(matches!(history.get(7), Err(HistoryError::NotFound(7))));
+}
+
+#[test]
+fn test_latest_entry() {
+    let dir = TempDir::new().unwrap();
+    let history = History::new(dir.path().join("history.jsonl"), 10);
+    assert!(history.latest().unwrap().is_none());
+
+    history.record(entry("first")).unwrap();
+    history.record(entry("second")).unwrap();
+    assert_eq!(
+        history.latest().unwrap().unwrap().answer,
+        "Answer to second"
+    );
+}
+
+#[test]
+fn test_format_time() {
+    assert_eq!(format_time(0), "1970-01-01 00:00");
+    assert_eq!(format_time(1_700_000_000), "2023-11-14 22:13");
+    assert_eq!(format_time(951_782_400), "2000-02-29 00:00");
+}
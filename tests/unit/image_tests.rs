@@ -0,0 +1,49 @@
+use mergil::image::{sniff_mime, Image, ImageError, MAX_IMAGE_BYTES};
+use std::io::Write;
+
+const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+#[test]
+fn test_sniff_mime() {
+    assert_eq!(sniff_mime(PNG_HEADER), Some("image/png"));
+    assert_eq!(
+        sniff_mime(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+        Some("image/jpeg")
+    );
+    assert_eq!(sniff_mime(b"GIF89a\x01\0"), Some("image/gif"));
+    assert_eq!(sniff_mime(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+    assert_eq!(sniff_mime(b"%PDF-1.7"), None);
+    assert_eq!(sniff_mime(b""), None);
+}
+
+#[test]
+fn test_load_encodes_and_ignores_extension() {
+    let mut file = tempfile::Builder::new().suffix(".jpg").tempfile().unwrap();
+    file.write_all(PNG_HEADER).unwrap();
+
+    let image = Image::load(file.path()).unwrap();
+    assert_eq!(image.mime, "image/png");
+    assert_eq!(image.data, "iVBORw0KGgoAAAANSUhEUg==");
+    assert_eq!(
+        image.data_url(),
+        "data:image/png;base64,iVBORw0KGgoAAAANSUhEUg=="
+    );
+}
+
+#[test]
+fn test_load_rejects_unknown_format() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"just some text").unwrap();
+
+    let result = Image::load(file.path());
+    assert!(matches!(result, Err(ImageError::UnsupportedFormat(_))));
+}
+
+#[test]
+fn test_load_rejects_large_file() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    file.as_file().set_len(MAX_IMAGE_BYTES + 1).unwrap();
+
+    let result = Image::load(file.path());
+    assert!(matches!(result, Err(ImageError::TooLarge(_, size)) if size == MAX_IMAGE_BYTES + 1));
+}
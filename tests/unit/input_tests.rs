@@ -1,4 +1,7 @@
-use mergil::input::{get_input, EditorOpener, InputResult, StdinReader};
+use mergil::input::{
+    editor_command, get_input, get_input_with_template, EditorOpener, EditorStatus, EditorTemplate,
+    InputResult, StdinReader,
+};
 use std::{cell::RefCell, io};
 
 struct MockStdin {
@@ -23,9 +26,9 @@ struct MockEditor {
 }
 
 impl EditorOpener for MockEditor {
-    fn open_editor(&self, temp_path: &str) -> io::Result<()> {
+    fn open_editor(&self, temp_path: &str) -> io::Result<EditorStatus> {
         std::fs::write(temp_path, self.content.borrow().as_bytes())?;
-        Ok(())
+        Ok(EditorStatus::Saved)
     }
 }
 
@@ -88,3 +91,174 @@ fn test_get_input_force_editor_empty() {
 
     assert!(matches!(result, InputResult::Cancelled));
 }
+
+/// Writes its content below whatever the buffer was prefilled with, and
+/// remembers the path and the prefilled text.
+struct AppendingEditor {
+    content: String,
+    seen: RefCell<Option<(String, String)>>,
+}
+
+impl EditorOpener for AppendingEditor {
+    fn open_editor(&self, temp_path: &str) -> io::Result<EditorStatus> {
+        let prefilled = std::fs::read_to_string(temp_path)?;
+        *self.seen.borrow_mut() = Some((temp_path.to_string(), prefilled.clone()));
+        std::fs::write(temp_path, format!("{}{}", self.content, prefilled))?;
+        Ok(EditorStatus::Saved)
+    }
+}
+
+fn template() -> EditorTemplate {
+    EditorTemplate {
+        model: Some("test/model".to_string()),
+        files: vec!["shot.png".into()],
+        stdin: Some("fn main() {}\n".to_string()),
+        last_reply: None,
+        comment_prefix: None,
+    }
+}
+
+#[test]
+fn test_editor_template_header() {
+    let header = template().header();
+    assert!(header.starts_with("\n# Write your prompt above."));
+    assert!(header.contains("# Model: test/model\n"));
+    assert!(header.contains("# File: shot.png\n"));
+    assert!(header.contains("# Piped input:\n#   fn main() {}\n"));
+    assert!(!header.contains("Last reply"));
+    assert!(header.lines().skip(1).all(|line| line.starts_with('#')));
+}
+
+#[test]
+fn test_editor_template_header_truncates_preview() {
+    let template = EditorTemplate {
+        last_reply: Some((1..=20).map(|i| format!("line {}\n", i)).collect()),
+        ..EditorTemplate::default()
+    };
+    let header = template.header();
+    assert!(header.contains("# Last reply:\n#   line 1\n"));
+    assert!(header.contains("#   line 10\n#   ...\n"));
+    assert!(!header.contains("line 11"));
+}
+
+#[test]
+fn test_editor_template_empty_without_context() {
+    assert_eq!(EditorTemplate::default().header(), "");
+}
+
+#[test]
+fn test_get_input_strips_comments() {
+    let mut mock_stdin = MockStdin {
+        content: RefCell::new(String::new()),
+        is_atty: true,
+    };
+    let editor = AppendingEditor {
+        content: "Explain this\n# scratch note\nplease".to_string(),
+        seen: RefCell::new(None),
+    };
+
+    let result = get_input_with_template(true, &mut mock_stdin, &editor, &template()).unwrap();
+
+    assert!(matches!(result, InputResult::Content(content) if content == "Explain this\nplease"));
+    let (path, prefilled) = editor.seen.borrow().clone().unwrap();
+    assert!(path.ends_with(".md"));
+    assert_eq!(prefilled, template().header());
+}
+
+#[test]
+fn test_get_input_custom_comment_prefix() {
+    let mut mock_stdin = MockStdin {
+        content: RefCell::new(String::new()),
+        is_atty: true,
+    };
+    let editor = AppendingEditor {
+        content: "# Heading\nBody\n".to_string(),
+        seen: RefCell::new(None),
+    };
+    let template = EditorTemplate {
+        comment_prefix: Some(";".to_string()),
+        ..template()
+    };
+
+    let result = get_input_with_template(true, &mut mock_stdin, &editor, &template).unwrap();
+
+    assert!(matches!(result, InputResult::Content(content) if content == "# Heading\nBody"));
+    assert!(editor
+        .seen
+        .borrow()
+        .as_ref()
+        .unwrap()
+        .1
+        .contains("; Model: test/model"));
+}
+
+#[test]
+fn test_get_input_only_comments_cancels() {
+    let mut mock_stdin = MockStdin {
+        content: RefCell::new(String::new()),
+        is_atty: true,
+    };
+    let editor = AppendingEditor {
+        content: String::new(),
+        seen: RefCell::new(None),
+    };
+
+    let result = get_input_with_template(true, &mut mock_stdin, &editor, &template()).unwrap();
+
+    assert!(matches!(result, InputResult::Cancelled));
+}
+
+/// Writes its content, then exits the way `:cq` does in vim.
+struct AbortingEditor;
+
+impl EditorOpener for AbortingEditor {
+    fn open_editor(&self, temp_path: &str) -> io::Result<EditorStatus> {
+        std::fs::write(temp_path, "Half-written prompt")?;
+        Ok(EditorStatus::Aborted)
+    }
+}
+
+#[test]
+fn test_get_input_aborted_editor_cancels() {
+    let mut mock_stdin = MockStdin {
+        content: RefCell::new(String::new()),
+        is_atty: true,
+    };
+
+    let result = get_input(true, &mut mock_stdin, &AbortingEditor).unwrap();
+
+    assert!(matches!(result, InputResult::Cancelled));
+}
+
+#[test]
+fn test_editor_command_splits_arguments() {
+    assert_eq!(
+        editor_command("emacsclient -t").unwrap(),
+        vec!["emacsclient", "-t"]
+    );
+    assert_eq!(
+        editor_command("'/opt/My Editor/bin/nvim' --clean").unwrap(),
+        vec!["/opt/My Editor/bin/nvim", "--clean"]
+    );
+    assert_eq!(editor_command("vi").unwrap(), vec!["vi"]);
+}
+
+#[test]
+fn test_editor_command_adds_wait_flags() {
+    assert_eq!(editor_command("code").unwrap(), vec!["code", "--wait"]);
+    assert_eq!(
+        editor_command("code --wait").unwrap(),
+        vec!["code", "--wait"]
+    );
+    assert_eq!(
+        editor_command("/usr/local/bin/subl -n").unwrap(),
+        vec!["/usr/local/bin/subl", "-n", "-w"]
+    );
+    assert_eq!(editor_command("gvim").unwrap(), vec!["gvim", "-f"]);
+}
+
+#[test]
+fn test_editor_command_rejects_invalid() {
+    assert!(editor_command("").is_err());
+    assert!(editor_command("vim 'unterminated").is_err());
+}
@@ -0,0 +1,42 @@
+use mergil::interrupt::{self, Interrupts, Press};
+use std::future;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_press_with_nothing_in_flight_exits() {
+    let interrupts = Interrupts::default();
+    assert_eq!(interrupts.press(), Press::Exit);
+}
+
+#[tokio::test]
+async fn test_press_cancels_request_in_flight() {
+    let interrupts = Arc::new(Interrupts::default());
+
+    let request = tokio::spawn({
+        let interrupts = interrupts.clone();
+        async move { interrupts.interruptible(future::pending::<()>()).await }
+    });
+    while interrupts.press() == Press::Exit {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(request.await.unwrap(), None);
+    // The cancelled request is no longer in flight.
+    assert_eq!(interrupts.press(), Press::Exit);
+}
+
+#[tokio::test]
+async fn test_interruptible_completes() {
+    let interrupts = Interrupts::default();
+    assert_eq!(interrupts.interruptible(async { 42 }).await, Some(42));
+    assert_eq!(interrupts.press(), Press::Exit);
+}
+
+#[tokio::test]
+async fn test_interruptible_without_handler_runs_to_completion() {
+    assert_eq!(
+        interrupt::interruptible(async { "done" }).await,
+        Some("done")
+    );
+}
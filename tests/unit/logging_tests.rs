@@ -0,0 +1,147 @@
+use mergil::api::{self, ChatRequest};
+use mergil::logging::{self, LogOptions, LoggingError};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use std::io;
+use std::sync::{Arc, Mutex};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Collects log output for assertions.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Buffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+async fn stream_with_logging(verbosity: u8, http: bool) -> String {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\ndata: [DONE]\n\n",
+            "text/event-stream",
+        ))
+        .mount(&server)
+        .await;
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let options = LogOptions {
+        verbosity,
+        http,
+        ..LogOptions::default()
+    };
+    let filter = logging::filter(&options, None).unwrap();
+    let _default = tracing::subscriber::set_default(logging::subscriber(
+        filter,
+        move || writer.clone(),
+        false,
+    ));
+
+    let request = ChatRequest {
+        model: "test-model".to_string(),
+        contents: vec!["Hello".to_string()],
+        ..ChatRequest::default()
+    };
+    let url = format!("{}/v1/chat/completions", server.uri());
+    api::stream_api_request(
+        &reqwest::Client::new(),
+        "sk-secret-key",
+        &request,
+        Some(&url),
+        &mut |_: &str| {},
+    )
+    .await
+    .unwrap();
+
+    buffer.text()
+}
+
+#[test]
+fn test_default_directives() {
+    assert_eq!(logging::default_directives(0), "warn");
+    assert_eq!(logging::default_directives(1), "warn,mergil=info");
+    assert_eq!(logging::default_directives(2), "warn,mergil=debug");
+    assert_eq!(logging::default_directives(5), "info,mergil=trace");
+}
+
+#[test]
+fn test_filter_keeps_http_off_without_the_flag() {
+    let filter = logging::filter(&LogOptions::default(), Some("trace"))
+        .unwrap()
+        .to_string();
+    assert!(filter.contains("mergil::http=off"), "{}", filter);
+
+    let options = LogOptions {
+        http: true,
+        ..LogOptions::default()
+    };
+    let filter = logging::filter(&options, None).unwrap().to_string();
+    assert!(filter.contains("mergil::http=trace"), "{}", filter);
+}
+
+#[test]
+fn test_filter_quiet_logs_errors_only() {
+    let options = LogOptions {
+        verbosity: 2,
+        quiet: true,
+        ..LogOptions::default()
+    };
+    let filter = logging::filter(&options, None).unwrap().to_string();
+    assert_eq!(filter, "mergil::http=off,error");
+}
+
+#[test]
+fn test_filter_rejects_invalid_rust_log() {
+    let error = logging::filter(&LogOptions::default(), Some("mergil=loud")).unwrap_err();
+    assert!(matches!(error, LoggingError::InvalidFilter(..)));
+    assert!(error
+        .to_string()
+        .starts_with("Invalid RUST_LOG filter `mergil=loud`"));
+}
+
+#[test]
+fn test_format_headers_redacts_authorization() {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer sk-secret"));
+
+    assert_eq!(
+        logging::format_headers(&headers),
+        "content-type: application/json\nauthorization: [REDACTED]"
+    );
+}
+
+#[tokio::test]
+async fn test_request_span_is_timed() {
+    let log = stream_with_logging(1, false).await;
+
+    assert!(log.contains("request{model=test-model}"), "{}", log);
+    assert!(log.contains("close time.busy="), "{}", log);
+    assert!(!log.contains("> POST"), "{}", log);
+}
+
+#[tokio::test]
+async fn test_log_http_redacts_authorization() {
+    let log = stream_with_logging(0, true).await;
+
+    assert!(log.contains("> POST"), "{}", log);
+    assert!(log.contains("authorization: [REDACTED]"), "{}", log);
+    assert!(log.contains("\"content\":\"Hello\""), "{}", log);
+    assert!(log.contains("< 200 OK"), "{}", log);
+    assert!(log.contains("< data: [DONE]"), "{}", log);
+    assert!(!log.contains("sk-secret-key"), "{}", log);
+}
@@ -1,20 +1,108 @@
+use clap::Parser;
+use mergil::api::ApiError;
 use mergil::common::Cli;
-use std::env;
+use mergil::config::Config;
+use mergil::provider::MockProvider;
 
 #[tokio::test]
 async fn test_run() {
-    env::set_var("RUST_TEST", "1");
+    let cli = Cli::parse_from([
+        "mergil",
+        "--model",
+        "deepseek/deepseek-coder",
+        "--debug",
+        "Test input",
+    ]);
+    let provider = MockProvider::new().with_response("Test output");
+    let mut out = Vec::new();
 
-    let cli = Cli {
-        context: vec!["Test input".to_string()],
-        model: "deepseek/deepseek-coder".to_string(),
-        debug: true,
-        markdown: false,
-        preprocess: false,
-    };
-
-    let result = mergil::run(cli).await;
+    let result = mergil::run_with_provider(cli, &Config::default(), &provider, &mut out).await;
     assert!(result.is_ok());
+    assert_eq!(String::from_utf8(out).unwrap(), "Test output\n");
+}
+
+#[tokio::test]
+async fn test_run_falls_back_through_config_models() {
+    let cli = Cli::parse_from(["mergil", "Test input"]);
+    let config: Config = toml::from_str(
+        r#"
+model = ["sonnet", "openai/gpt-4o"]
+
+[aliases]
+sonnet = "anthropic/claude-3.5-sonnet"
+"#,
+    )
+    .unwrap();
+    let provider = MockProvider::new()
+        .with_error(ApiError::ModelUnavailable("down".to_string()))
+        .with_response("Fallback output");
+    let mut out = Vec::new();
+
+    mergil::run_with_provider(cli, &config, &provider, &mut out)
+        .await
+        .unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap(), "Fallback output\n");
+    let models: Vec<String> = provider.requests().into_iter().map(|r| r.model).collect();
+    assert_eq!(models, vec!["anthropic/claude-3.5-sonnet", "openai/gpt-4o"]);
+}
+
+#[tokio::test]
+async fn test_run_prints_truncated_answer_then_fails() {
+    let cli = Cli::parse_from(["mergil", "--quiet", "Test input"]);
+    let provider = MockProvider::new()
+        .with_response("Cut off")
+        .with_finish_reason("length");
+    let mut out = Vec::new();
+
+    let error = mergil::run_with_provider(cli, &Config::default(), &provider, &mut out)
+        .await
+        .unwrap_err();
+
+    assert_eq!(String::from_utf8(out).unwrap(), "Cut off\n");
+    assert!(matches!(
+        error.downcast_ref::<ApiError>(),
+        Some(ApiError::Truncated)
+    ));
+    assert_eq!(mergil::output::exit_code(&*error), 7);
+}
+
+#[tokio::test]
+async fn test_run_prints_answer_when_copy_fails() {
+    let cli = Cli::parse_from(["mergil", "--copy", "--code-only", "Test input"]);
+    let provider = MockProvider::new().with_response("No code here");
+    let mut out = Vec::new();
+
+    mergil::run_with_provider(cli, &Config::default(), &provider, &mut out)
+        .await
+        .unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap(), "No code here\n");
+}
+
+#[tokio::test]
+async fn test_run_does_not_append_fallback_to_partial_answer() {
+    let cli = Cli::parse_from([
+        "mergil",
+        "--quiet",
+        "--output",
+        "ndjson",
+        "--model",
+        "first/model,second/model",
+        "Test input",
+    ]);
+    let provider = MockProvider::new()
+        .with_partial("Half an ", ApiError::ModelUnavailable("died".to_string()))
+        .with_response("Other answer");
+    let mut out = Vec::new();
+
+    let error = mergil::run_with_provider(cli, &Config::default(), &provider, &mut out)
+        .await
+        .unwrap_err();
 
-    env::remove_var("RUST_TEST");
+    assert!(error.to_string().contains("died"));
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(r#""content":"Half ""#));
+    assert!(!out.contains("Other answer"));
+    assert_eq!(provider.requests().len(), 1);
 }
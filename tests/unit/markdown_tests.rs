@@ -30,3 +30,13 @@ fn test_create_madskin() {
         Some(rgb(30, 30, 30))
     );
 }
+
+#[test]
+fn test_code_blocks() {
+    let text = "Run this:\n\n```sh\nls -la\ncd /tmp\n```\n\nThen:\n  ```\necho done\n  ```\n";
+    assert_eq!(
+        mergil::markdown::code_blocks(text),
+        vec!["ls -la\ncd /tmp".to_string(), "echo done".to_string()]
+    );
+    assert!(mergil::markdown::code_blocks("No code here").is_empty());
+}
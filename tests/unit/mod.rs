@@ -1,5 +1,25 @@
 mod api_tests;
+mod chunked_tests;
+mod clipboard_tests;
 mod common_tests;
+mod completions_tests;
+mod config_tests;
+mod context_tests;
+mod history_tests;
+mod image_tests;
 mod input_tests;
+mod interrupt_tests;
+mod logging_tests;
 mod main_tests;
 mod markdown_tests;
+mod models_tests;
+mod output_tests;
+mod pipeline_tests;
+mod prompts_tests;
+mod provider_tests;
+mod redact_tests;
+mod schema_tests;
+mod server_tests;
+mod shell_tests;
+mod template_tests;
+mod terminal_tests;
@@ -0,0 +1,237 @@
+use mergil::models::{self, Catalog, ListFormat, Model, ModelCache, ModelsError};
+use std::time::Duration;
+use tempfile::TempDir;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn model(id: &str) -> Model {
+    Model {
+        id: id.to_string(),
+        context_length: None,
+        prompt_price: None,
+        completion_price: None,
+        modality: None,
+    }
+}
+
+fn catalog() -> Catalog {
+    Catalog {
+        source: "https://openrouter.ai/api/v1".to_string(),
+        fetched: 1_000_000,
+        models: vec![
+            model("anthropic/claude-3.5-sonnet"),
+            model("deepseek/deepseek-coder"),
+            model("deepseek/deepseek-chat"),
+            model("openai/gpt-4o"),
+        ],
+    }
+}
+
+#[test]
+fn test_parse_openai() {
+    let body = r#"{"data": [
+        {
+            "id": "anthropic/claude-3.5-sonnet",
+            "context_length": 200000,
+            "pricing": {"prompt": "0.000003", "completion": "0.000015"},
+            "architecture": {"modality": "text+image->text"}
+        },
+        {"id": "gpt-4o"}
+    ]}"#;
+
+    let models = models::parse_openai(body).unwrap();
+
+    assert_eq!(models[0].id, "anthropic/claude-3.5-sonnet");
+    assert_eq!(models[0].context_length, Some(200_000));
+    assert!((models[0].prompt_price.unwrap() - 3.0).abs() < 1e-9);
+    assert!((models[0].completion_price.unwrap() - 15.0).abs() < 1e-9);
+    assert_eq!(models[0].modality.as_deref(), Some("text+image->text"));
+    assert_eq!(models[1], model("gpt-4o"));
+}
+
+#[test]
+fn test_catalog_context_length() {
+    let mut catalog = catalog();
+    catalog.models[0].context_length = Some(200_000);
+
+    assert_eq!(
+        catalog.context_length("anthropic/claude-3.5-sonnet"),
+        Some(200_000)
+    );
+    assert_eq!(
+        catalog.context_length("anthropic/claude-3.5-sonnet:beta"),
+        Some(200_000)
+    );
+    assert_eq!(catalog.context_length("openai/gpt-4o"), None);
+    assert_eq!(catalog.context_length("local/unknown"), None);
+}
+
+#[test]
+fn test_catalog_accepts_images() {
+    let mut catalog = catalog();
+    catalog.models[0].modality = Some("text+image->text".to_string());
+    catalog.models[1].modality = Some("text->text".to_string());
+    catalog.models[2].modality = Some("text->text+image".to_string());
+
+    assert_eq!(
+        catalog.accepts_images("anthropic/claude-3.5-sonnet:beta"),
+        Some(true)
+    );
+    assert_eq!(
+        catalog.accepts_images("deepseek/deepseek-coder"),
+        Some(false)
+    );
+    assert_eq!(
+        catalog.accepts_images("deepseek/deepseek-chat"),
+        Some(false)
+    );
+    assert_eq!(catalog.accepts_images("openai/gpt-4o"), None);
+    assert_eq!(catalog.accepts_images("local/unknown"), None);
+}
+
+#[test]
+fn test_parse_ollama() {
+    let body = r#"{"models": [{"name": "qwen2.5:7b", "size": 4683087332}]}"#;
+
+    let models = models::parse_ollama(body).unwrap();
+
+    assert_eq!(models.len(), 1);
+    assert_eq!(models[0].id, "qwen2.5:7b");
+    assert_eq!(models[0].prompt_price, Some(0.0));
+}
+
+#[test]
+fn test_list_format_of() {
+    assert_eq!(
+        ListFormat::of("http://localhost:11434/v1"),
+        ListFormat::Ollama
+    );
+    assert_eq!(
+        ListFormat::of("https://openrouter.ai/api/v1"),
+        ListFormat::OpenAi
+    );
+}
+
+#[test]
+fn test_search_ignores_case() {
+    let catalog = catalog();
+    let ids: Vec<&str> = catalog
+        .search("DeepSeek")
+        .iter()
+        .map(|m| m.id.as_str())
+        .collect();
+    assert_eq!(
+        ids,
+        vec!["deepseek/deepseek-coder", "deepseek/deepseek-chat"]
+    );
+}
+
+#[test]
+fn test_validate_accepts_known_models_and_suffixes() {
+    let catalog = catalog();
+    assert!(catalog.validate("openai/gpt-4o").is_ok());
+    assert!(catalog.validate("openai/gpt-4o:online").is_ok());
+}
+
+#[test]
+fn test_validate_suggests_close_matches() {
+    let catalog = catalog();
+
+    let error = catalog.validate("deepseek/deepseek-codr").unwrap_err();
+    assert!(matches!(
+        &error,
+        ModelsError::Unknown { suggestions, .. } if suggestions[0] == "deepseek/deepseek-coder"
+    ));
+    assert!(error
+        .to_string()
+        .starts_with("Unknown model deepseek/deepseek-codr. Did you mean deepseek/deepseek-coder"));
+
+    assert_eq!(
+        catalog.suggest("claude-3.5-sonnet"),
+        vec!["anthropic/claude-3.5-sonnet"]
+    );
+    assert!(catalog.suggest("something-else-entirely").is_empty());
+}
+
+#[test]
+fn test_catalog_freshness() {
+    let catalog = catalog();
+    assert!(catalog.is_fresh("https://openrouter.ai/api/v1", 1_000_000 + 60));
+    assert!(!catalog.is_fresh(
+        "https://openrouter.ai/api/v1",
+        1_000_000 + models::CACHE_MAX_AGE_SECS
+    ));
+    assert!(!catalog.is_fresh("http://localhost:11434/v1", 1_000_000));
+}
+
+#[test]
+fn test_cache_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let cache = ModelCache::new(dir.path().join("nested").join("models.json"));
+
+    assert_eq!(cache.load().unwrap(), None);
+    cache.save(&catalog()).unwrap();
+    assert_eq!(cache.load().unwrap(), Some(catalog()));
+}
+
+#[test]
+fn test_format() {
+    assert_eq!(models::format_tokens(200_000), "200k");
+    assert_eq!(models::format_tokens(131_072), "131k");
+    assert_eq!(models::format_tokens(1_000_000), "1M");
+    assert_eq!(models::format_tokens(512), "512");
+    assert_eq!(models::format_price(Some(3.0)), "$3.00");
+    assert_eq!(models::format_price(Some(0.0)), "free");
+    assert_eq!(models::format_price(None), "-");
+}
+
+#[tokio::test]
+async fn test_fetch_openai_format() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/models"))
+        .and(header("Authorization", "Bearer key"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({"data": [{"id": "openai/gpt-4o"}]})),
+        )
+        .mount(&server)
+        .await;
+    let client = mergil::api::build_client(Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+
+    let models = models::fetch(
+        &client,
+        &format!("{}/api/v1/", server.uri()),
+        "key",
+        ListFormat::OpenAi,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(models, vec![model("openai/gpt-4o")]);
+}
+
+#[tokio::test]
+async fn test_fetch_ollama_format() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/tags"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({"models": [{"name": "llama3.2:3b"}]})),
+        )
+        .mount(&server)
+        .await;
+    let client = mergil::api::build_client(Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+
+    let models = models::fetch(
+        &client,
+        &format!("{}/v1", server.uri()),
+        "",
+        ListFormat::Ollama,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(models[0].id, "llama3.2:3b");
+}
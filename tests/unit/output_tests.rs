@@ -0,0 +1,112 @@
+use mergil::api::{ApiError, Completion, Usage};
+use mergil::output::{self, Event, Report};
+
+fn report() -> Report {
+    Report::new(
+        Completion {
+            content: "Hello".to_string(),
+            model: Some("test-model".to_string()),
+            usage: Some(Usage {
+                prompt_tokens: 1,
+                completion_tokens: 2,
+                total_tokens: 3,
+            }),
+            finish_reason: Some("stop".to_string()),
+        },
+        42,
+        "session",
+    )
+}
+
+#[test]
+fn test_report_json() {
+    let json = serde_json::to_value(report()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "content": "Hello",
+            "model": "test-model",
+            "usage": {"prompt_tokens": 1, "completion_tokens": 2, "total_tokens": 3},
+            "finish_reason": "stop",
+            "latency_ms": 42,
+            "session_id": "session",
+        })
+    );
+}
+
+#[test]
+fn test_ndjson_events() {
+    let delta = serde_json::to_value(Event::Delta { content: "Hel" }).unwrap();
+    assert_eq!(
+        delta,
+        serde_json::json!({"type": "delta", "content": "Hel"})
+    );
+
+    let report = report();
+    let done = serde_json::to_value(Event::Done(&report)).unwrap();
+    assert_eq!(done["type"], "done");
+    assert_eq!(done["content"], "Hello");
+    assert_eq!(done["latency_ms"], 42);
+}
+
+#[test]
+fn test_error_json() {
+    let error: Box<dyn std::error::Error> = Box::new(ApiError::RateLimited("slow down".into()));
+    assert_eq!(
+        output::error_json(&*error),
+        serde_json::json!({
+            "type": "error",
+            "error": {"code": "rate_limited", "message": "Rate limited: slow down"}
+        })
+    );
+
+    let error: Box<dyn std::error::Error> = "something else".into();
+    assert_eq!(output::error_code(&*error), "error");
+}
+
+#[test]
+fn test_exit_code() {
+    use mergil::pipeline::PipelineError;
+    use mergil::prompts::PromptError;
+
+    let cases: Vec<(Box<dyn std::error::Error>, i32)> = vec![
+        (ApiError::Unauthorized("no".to_string()).into(), 3),
+        (ApiError::MissingApiKey("unset".to_string()).into(), 3),
+        (ApiError::RateLimited("slow down".to_string()).into(), 4),
+        (ApiError::RetryExhausted.into(), 5),
+        (ApiError::Cancelled.into(), 6),
+        (ApiError::Truncated.into(), 7),
+        (ApiError::ModelUnavailable("down".to_string()).into(), 1),
+        (
+            PipelineError::StepFailed("a".to_string(), ApiError::Cancelled).into(),
+            6,
+        ),
+        (
+            PipelineError::NotFound("nope".to_string(), Vec::new()).into(),
+            2,
+        ),
+        (
+            PromptError::UnknownPersona("nope".to_string(), Vec::new()).into(),
+            2,
+        ),
+        ("something else".into(), 1),
+    ];
+
+    for (error, code) in cases {
+        assert_eq!(output::exit_code(&*error), code, "{}", error);
+    }
+}
+
+#[test]
+fn test_is_broken_pipe() {
+    let error: Box<dyn std::error::Error> =
+        std::io::Error::from(std::io::ErrorKind::BrokenPipe).into();
+    assert!(output::is_broken_pipe(&*error));
+
+    let error: Box<dyn std::error::Error> =
+        std::io::Error::from(std::io::ErrorKind::NotFound).into();
+    assert!(!output::is_broken_pipe(&*error));
+    assert!(!output::is_broken_pipe(
+        &*Box::<dyn std::error::Error>::from("Broken pipe")
+    ));
+}
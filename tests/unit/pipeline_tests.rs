@@ -0,0 +1,277 @@
+use async_trait::async_trait;
+use mergil::api::{ApiError, Completion};
+use mergil::config::{StepConfig, StepInput, StepOutput};
+use mergil::pipeline::{self, Pipeline, PipelineError, Refine, Stage};
+use mergil::provider::{ChatRequest, MockProvider, Provider};
+
+fn request() -> ChatRequest {
+    ChatRequest {
+        model: "main/model".to_string(),
+        system: vec!["Be brief".to_string()],
+        contents: vec!["Write a haiku about Rust".to_string()],
+        ..ChatRequest::default()
+    }
+}
+
+#[tokio::test]
+async fn test_refine_revises_until_approved() {
+    let provider = MockProvider::new()
+        .with_response("Draft haiku")
+        .with_response("The second line has too many syllables.")
+        .with_response("Better haiku")
+        .with_response("APPROVED");
+    let pipeline = Pipeline::new().then(Refine::new("critic/model", 3));
+
+    let answer = pipeline.run(&provider, &request()).await.unwrap();
+
+    assert_eq!(answer.content, "Better haiku");
+    let requests = provider.requests();
+    assert_eq!(requests.len(), 4);
+    assert_eq!(requests[1].model, "critic/model");
+    assert_eq!(
+        requests[1].contents,
+        vec![
+            "Request:\n\nWrite a haiku about Rust",
+            "Answer:\n\nDraft haiku"
+        ]
+    );
+    assert_eq!(requests[2].model, "main/model");
+    assert_eq!(requests[2].system, vec!["Be brief"]);
+    assert!(requests[2].contents[1].contains("Draft haiku"));
+    assert!(requests[2].contents[1].contains("too many syllables"));
+    assert_eq!(requests[3].contents[1], "Answer:\n\nBetter haiku");
+}
+
+#[tokio::test]
+async fn test_refine_stops_after_rounds() {
+    let provider = MockProvider::new()
+        .with_response("Fix it")
+        .with_response("Revision 1")
+        .with_response("Still wrong")
+        .with_response("Revision 2");
+    let refine = Refine::new("critic/model", 2);
+
+    let initial = Completion {
+        content: "Draft".to_string(),
+        ..Completion::default()
+    };
+    let answer = refine.run(&provider, &request(), initial).await.unwrap();
+
+    assert_eq!(answer.content, "Revision 2");
+    assert_eq!(provider.requests().len(), 4);
+}
+
+#[tokio::test]
+async fn test_refine_accepts_formatted_approval() {
+    let provider = MockProvider::new().with_response("**APPROVED**");
+    let initial = Completion {
+        content: "Draft".to_string(),
+        ..Completion::default()
+    };
+
+    let answer = Refine::new("critic/model", 3)
+        .run(&provider, &request(), initial)
+        .await
+        .unwrap();
+
+    assert_eq!(answer.content, "Draft");
+    assert_eq!(provider.requests().len(), 1);
+}
+
+struct Shout;
+
+#[async_trait]
+impl Stage for Shout {
+    async fn run(
+        &self,
+        _provider: &dyn Provider,
+        _request: &ChatRequest,
+        answer: Completion,
+    ) -> Result<Completion, ApiError> {
+        Ok(Completion {
+            content: answer.content.to_uppercase(),
+            ..answer
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_pipeline_chains_custom_stages() {
+    let provider = MockProvider::new()
+        .with_response("draft")
+        .with_response("APPROVED");
+    let pipeline = Pipeline::new()
+        .then(Refine::new("critic/model", 1))
+        .then(Shout);
+
+    let answer = pipeline.run(&provider, &request()).await.unwrap();
+
+    assert_eq!(answer.content, "DRAFT");
+}
+
+fn step(name: &str) -> StepConfig {
+    StepConfig {
+        name: Some(name.to_string()),
+        ..StepConfig::default()
+    }
+}
+
+#[test]
+fn test_plan_runs_steps_in_order_by_default() {
+    let steps = vec![step("a"), step("b"), step("c")];
+
+    let plan = pipeline::plan(&steps).unwrap();
+
+    assert_eq!(plan.waves, vec![vec![0], vec![1], vec![2]]);
+    assert_eq!(plan.dependencies, vec![vec![], vec![0], vec![1]]);
+}
+
+#[test]
+fn test_plan_groups_independent_steps() {
+    let steps = vec![
+        StepConfig {
+            input: StepInput::Original,
+            ..step("bugs")
+        },
+        StepConfig {
+            input: StepInput::Original,
+            ..step("style")
+        },
+        StepConfig {
+            after: Some(vec!["bugs".to_string(), "style".to_string()]),
+            ..step("summary")
+        },
+    ];
+
+    let plan = pipeline::plan(&steps).unwrap();
+
+    assert_eq!(plan.waves, vec![vec![0, 1], vec![2]]);
+}
+
+#[test]
+fn test_plan_rejects_bad_dependencies() {
+    let unknown = vec![StepConfig {
+        after: Some(vec!["missing".to_string()]),
+        ..step("a")
+    }];
+    assert!(matches!(
+        pipeline::plan(&unknown),
+        Err(PipelineError::UnknownStep { .. })
+    ));
+
+    let duplicate = vec![step("a"), step("a")];
+    assert!(matches!(
+        pipeline::plan(&duplicate),
+        Err(PipelineError::DuplicateStep(name)) if name == "a"
+    ));
+
+    let cycle = vec![
+        StepConfig {
+            after: Some(vec!["b".to_string()]),
+            ..step("a")
+        },
+        step("b"),
+    ];
+    assert!(matches!(
+        pipeline::plan(&cycle),
+        Err(PipelineError::Cycle(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_run_steps_maps_inputs() {
+    let provider = MockProvider::new()
+        .with_response("Bug on line 3")
+        .with_response("Naming is fine")
+        .with_response("```rust\nfn fixed() {}\n```");
+    let steps = vec![
+        StepConfig {
+            model: Some("bugs/model".to_string()),
+            system: Some("Find bugs".to_string()),
+            input: StepInput::Original,
+            ..step("bugs")
+        },
+        StepConfig {
+            input: StepInput::Original,
+            ..step("style")
+        },
+        StepConfig {
+            after: Some(vec!["bugs".to_string(), "style".to_string()]),
+            input: StepInput::Both,
+            output: StepOutput::Code,
+            ..step("fix")
+        },
+    ];
+    let mut progress = Vec::new();
+
+    let output = pipeline::run_steps(
+        &provider,
+        &steps,
+        &["fn broken() {}".to_string()],
+        "default/model",
+        &mut progress,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(output, "fn fixed() {}");
+    let requests = provider.requests();
+    assert_eq!(requests[0].model, "bugs/model");
+    assert_eq!(requests[0].system, vec!["Find bugs"]);
+    assert_eq!(requests[0].contents, vec!["fn broken() {}"]);
+    assert_eq!(requests[1].model, "default/model");
+    assert!(requests[1].system.is_empty());
+    assert_eq!(
+        requests[2].contents,
+        vec!["fn broken() {}", "Bug on line 3", "Naming is fine"]
+    );
+    let progress = String::from_utf8(progress).unwrap();
+    assert!(progress.contains("Finished step fix (3/3)"));
+}
+
+#[tokio::test]
+async fn test_run_steps_extracts_json() {
+    let provider = MockProvider::new()
+        .with_response("Summary")
+        .with_response("```json\n{\"ok\": true}\n```");
+    let steps = vec![
+        step("summary"),
+        StepConfig {
+            output: StepOutput::Json,
+            ..step("verdict")
+        },
+    ];
+
+    let output = pipeline::run_steps(
+        &provider,
+        &steps,
+        &["Input".to_string()],
+        "default/model",
+        &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(output, "{\n  \"ok\": true\n}");
+    assert_eq!(provider.requests()[1].contents, vec!["Summary"]);
+}
+
+#[tokio::test]
+async fn test_run_steps_reports_failed_step() {
+    let provider = MockProvider::new().with_response("No JSON here");
+    let steps = vec![StepConfig {
+        output: StepOutput::Json,
+        ..step("verdict")
+    }];
+
+    let result = pipeline::run_steps(
+        &provider,
+        &steps,
+        &["Input".to_string()],
+        "default/model",
+        &mut Vec::new(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(PipelineError::InvalidJson(name, _)) if name == "verdict"));
+}
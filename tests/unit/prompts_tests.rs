@@ -0,0 +1,80 @@
+use mergil::config::{Config, PromptsConfig};
+use mergil::prompts::{self, PromptError, SystemPrompts};
+use std::collections::HashMap;
+use std::io::Write;
+
+#[test]
+fn test_default_messages() {
+    let prompts = SystemPrompts::default();
+
+    assert_eq!(
+        prompts.messages(false),
+        vec![
+            prompts::DEFAULT_SYSTEM.to_string(),
+            prompts::DEFAULT_PLAIN.to_string()
+        ]
+    );
+    assert_eq!(
+        prompts.messages(true),
+        vec![
+            prompts::DEFAULT_SYSTEM.to_string(),
+            prompts::DEFAULT_MARKDOWN.to_string()
+        ]
+    );
+    assert_eq!(
+        prompts.preprocess.as_deref(),
+        Some(prompts::DEFAULT_PREPROCESS)
+    );
+    assert!(SystemPrompts::none().messages(true).is_empty());
+}
+
+#[test]
+fn test_resolve_precedence() {
+    let config = Config {
+        prompts: PromptsConfig {
+            system: Some("config system".to_string()),
+            plain: Some("config plain".to_string()),
+            ..PromptsConfig::default()
+        },
+        personas: HashMap::from([("pirate".to_string(), "Talk like a pirate".to_string())]),
+        ..Config::default()
+    };
+
+    let prompts = SystemPrompts::resolve(None, None, &config).unwrap();
+    assert_eq!(
+        prompts.messages(false),
+        vec!["config system".to_string(), "config plain".to_string()]
+    );
+    assert_eq!(prompts.markdown.as_deref(), Some(prompts::DEFAULT_MARKDOWN));
+
+    let prompts = SystemPrompts::resolve(None, Some("pirate"), &config).unwrap();
+    assert_eq!(prompts.system.as_deref(), Some("Talk like a pirate"));
+
+    let prompts = SystemPrompts::resolve(None, Some("reviewer"), &config).unwrap();
+    assert!(prompts.system.unwrap().contains("code reviewer"));
+
+    let prompts = SystemPrompts::resolve(Some("flag system"), Some("pirate"), &config).unwrap();
+    assert_eq!(prompts.system.as_deref(), Some("flag system"));
+}
+
+#[test]
+fn test_resolve_system_from_file() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(file, "prompt from file").unwrap();
+    let arg = format!("@{}", file.path().display());
+
+    let prompts = SystemPrompts::resolve(Some(&arg), None, &Config::default()).unwrap();
+    assert_eq!(prompts.system.as_deref(), Some("prompt from file"));
+
+    let missing = SystemPrompts::resolve(Some("@/nonexistent/prompt"), None, &Config::default());
+    assert!(matches!(missing, Err(PromptError::ReadFailed(_, _))));
+}
+
+#[test]
+fn test_resolve_unknown_persona() {
+    let error = SystemPrompts::resolve(None, Some("poet"), &Config::default()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unknown persona 'poet', available: explainer, reviewer, shell"
+    );
+}
@@ -0,0 +1,232 @@
+use mergil::api::ApiError;
+use mergil::provider::{
+    ChatRequest, FallbackProvider, MockProvider, OpenRouterProvider, Provider, RoutingProvider,
+};
+use std::time::{Duration, Instant};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn request(content: &str) -> ChatRequest {
+    ChatRequest {
+        model: "test-model".to_string(),
+        contents: vec![content.to_string()],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_mock_provider_replays_script_in_order() {
+    let provider = MockProvider::new()
+        .with_response("first answer")
+        .with_error(ApiError::RetryExhausted)
+        .with_response("third answer");
+
+    let mut deltas = Vec::new();
+    let mut on_delta = |delta: &str| deltas.push(delta.to_string());
+    let first = provider.complete(&request("one"), &mut on_delta).await;
+    assert_eq!(first.unwrap().content, "first answer");
+    assert_eq!(deltas, vec!["first ", "answer"]);
+
+    let mut ignore = |_: &str| {};
+    let second = provider.complete(&request("two"), &mut ignore).await;
+    assert!(matches!(second, Err(ApiError::RetryExhausted)));
+
+    let third = provider.complete(&request("three"), &mut ignore).await;
+    assert_eq!(third.unwrap().content, "third answer");
+
+    let requests = provider.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[1], request("two"));
+}
+
+#[tokio::test]
+async fn test_mock_provider_errors_when_script_exhausted() {
+    let provider = MockProvider::new();
+
+    let mut ignore = |_: &str| {};
+    let result = provider.complete(&request("Hello"), &mut ignore).await;
+    assert!(matches!(result, Err(ApiError::ApiErrorResponse(_))));
+}
+
+#[tokio::test]
+async fn test_mock_provider_latency() {
+    let provider = MockProvider::new()
+        .with_response("slow")
+        .with_latency(Duration::from_millis(50));
+
+    let start = Instant::now();
+    let mut ignore = |_: &str| {};
+    provider
+        .complete(&request("Hello"), &mut ignore)
+        .await
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_openrouter_provider_uses_base_url() {
+    let mock_server = MockServer::start().await;
+    let body = "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\ndata: [DONE]\n\n";
+    Mock::given(method("POST"))
+        .and(path("/custom/v1/chat/completions"))
+        .and(header("Authorization", "Bearer test_key"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let provider = OpenRouterProvider::new(
+        reqwest::Client::new(),
+        "test_key".to_string(),
+        Some(format!("{}/custom/v1/", mock_server.uri())),
+    );
+    let mut ignore = |_: &str| {};
+    let result = provider.complete(&request("Hello"), &mut ignore).await;
+    assert_eq!(result.unwrap().content, "Hi");
+}
+
+fn chain() -> Vec<String> {
+    vec![
+        "first/model".to_string(),
+        "second/model".to_string(),
+        "third/model".to_string(),
+    ]
+}
+
+fn chain_request(model: &str) -> ChatRequest {
+    ChatRequest {
+        model: model.to_string(),
+        ..request("Hi")
+    }
+}
+
+#[tokio::test]
+async fn test_fallback_provider_tries_next_model() {
+    let mock = MockProvider::new()
+        .with_error(ApiError::RateLimited("busy".to_string()))
+        .with_error(ApiError::ModelUnavailable("down".to_string()))
+        .with_response("from the third");
+    let provider = FallbackProvider::new(&mock, chain());
+
+    let completion = provider
+        .complete(&chain_request("first/model"), &mut |_: &str| {})
+        .await
+        .unwrap();
+
+    assert_eq!(completion.content, "from the third");
+    assert_eq!(completion.model.as_deref(), Some("third/model"));
+    let models: Vec<String> = mock.requests().into_iter().map(|r| r.model).collect();
+    assert_eq!(models, chain());
+}
+
+#[tokio::test]
+async fn test_fallback_provider_stops_on_other_errors() {
+    let mock = MockProvider::new()
+        .with_error(ApiError::Unauthorized("bad key".to_string()))
+        .with_response("unused");
+    let provider = FallbackProvider::new(&mock, chain());
+
+    let result = provider
+        .complete(&chain_request("first/model"), &mut |_: &str| {})
+        .await;
+
+    assert!(matches!(result, Err(ApiError::Unauthorized(_))));
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_fallback_provider_returns_last_error() {
+    let mock = MockProvider::new()
+        .with_error(ApiError::RateLimited("busy".to_string()))
+        .with_error(ApiError::RateLimited("still busy".to_string()));
+    let provider = FallbackProvider::new(&mock, chain());
+
+    let result = provider
+        .complete(&chain_request("second/model"), &mut |_: &str| {})
+        .await;
+
+    assert!(matches!(result, Err(ApiError::RateLimited(message)) if message == "still busy"));
+    let models: Vec<String> = mock.requests().into_iter().map(|r| r.model).collect();
+    assert_eq!(models, vec!["second/model", "third/model"]);
+}
+
+#[tokio::test]
+async fn test_fallback_provider_does_not_fall_back_after_streaming() {
+    let mock = MockProvider::new()
+        .with_partial("Half an ", ApiError::ModelUnavailable("died".to_string()))
+        .with_response("unused");
+    let provider = FallbackProvider::new(&mock, chain());
+
+    let mut deltas = String::new();
+    let result = provider
+        .complete(&chain_request("first/model"), &mut |delta: &str| {
+            deltas.push_str(delta)
+        })
+        .await;
+
+    assert!(matches!(result, Err(ApiError::ModelUnavailable(_))));
+    assert_eq!(deltas, "Half an ");
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_fallback_provider_passes_other_models_through() {
+    let mock = MockProvider::new().with_error(ApiError::RateLimited("busy".to_string()));
+    let provider = FallbackProvider::new(&mock, chain());
+
+    let result = provider
+        .complete(&chain_request("cheap/model"), &mut |_: &str| {})
+        .await;
+
+    assert!(matches!(result, Err(ApiError::RateLimited(_))));
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_routing_provider_strips_configured_prefixes() {
+    let default = MockProvider::new()
+        .with_response("From the default")
+        .with_response("Tagged");
+    let local = MockProvider::new().with_response("From local");
+    let provider = RoutingProvider::new(&default).with_route("local", &local);
+
+    let routed = provider
+        .complete(&chain_request("local:qwen2.5"), &mut |_: &str| {})
+        .await
+        .unwrap();
+    assert_eq!(routed.content, "From local");
+    assert_eq!(routed.model.as_deref(), Some("local:qwen2.5"));
+    assert_eq!(local.requests()[0].model, "qwen2.5");
+
+    let plain = provider
+        .complete(&chain_request("openai/gpt-4o"), &mut |_: &str| {})
+        .await
+        .unwrap();
+    assert_eq!(plain.content, "From the default");
+
+    // Prefixes that name no provider are part of the model id.
+    provider
+        .complete(&chain_request("qwen2.5:7b"), &mut |_: &str| {})
+        .await
+        .unwrap();
+    let models: Vec<String> = default.requests().into_iter().map(|r| r.model).collect();
+    assert_eq!(models, vec!["openai/gpt-4o", "qwen2.5:7b"]);
+}
+
+#[tokio::test]
+async fn test_fallback_to_routed_model() {
+    let default = MockProvider::new().with_error(ApiError::RateLimited("busy".to_string()));
+    let local = MockProvider::new().with_response("Local answer");
+    let chain = vec!["openai/gpt-4o".to_string(), "local:qwen2.5".to_string()];
+    let provider = FallbackProvider::new(
+        RoutingProvider::new(&default).with_route("local", &local),
+        chain,
+    );
+
+    let completion = provider
+        .complete(&chain_request("openai/gpt-4o"), &mut |_: &str| {})
+        .await
+        .unwrap();
+    assert_eq!(completion.content, "Local answer");
+    assert_eq!(completion.model.as_deref(), Some("local:qwen2.5"));
+}
@@ -0,0 +1,72 @@
+use mergil::schema::{Schema, SchemaError};
+use std::io::Write;
+
+fn todo_schema() -> Schema {
+    Schema::from_value(
+        "todo list",
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "todos": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["todos"]
+        }),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_validate_accepts_matching_json() {
+    let value = todo_schema().validate(r#"{"todos": ["a", "b"]}"#).unwrap();
+    assert_eq!(value, serde_json::json!({"todos": ["a", "b"]}));
+}
+
+#[test]
+fn test_validate_strips_code_fence() {
+    let value = todo_schema()
+        .validate("```json\n{\"todos\": []}\n```\n")
+        .unwrap();
+    assert_eq!(value, serde_json::json!({"todos": []}));
+}
+
+#[test]
+fn test_validate_reports_errors_with_paths() {
+    let errors = todo_schema()
+        .validate(r#"{"todos": ["a", 2]}"#)
+        .unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("/todos/1: "), "{}", errors[0]);
+
+    let errors = todo_schema().validate("{}").unwrap_err();
+    assert!(errors[0].starts_with("/: "), "{}", errors[0]);
+}
+
+#[test]
+fn test_validate_rejects_invalid_json() {
+    let errors = todo_schema().validate("Here are your todos").unwrap_err();
+    assert!(errors[0].starts_with("not valid JSON"));
+}
+
+#[test]
+fn test_response_format_sanitizes_name() {
+    let format = todo_schema().response_format();
+    assert_eq!(format["type"], "json_schema");
+    assert_eq!(format["json_schema"]["name"], "todo_list");
+    assert_eq!(format["json_schema"]["schema"]["required"][0], "todos");
+}
+
+#[test]
+fn test_invalid_schema_is_rejected() {
+    let result = Schema::from_value("bad", serde_json::json!({"type": 12}));
+    assert!(matches!(result, Err(SchemaError::InvalidSchema(_, _))));
+}
+
+#[test]
+fn test_load_reads_file() {
+    let mut file = tempfile::Builder::new().suffix(".json").tempfile().unwrap();
+    write!(file, r#"{{"type": "string"}}"#).unwrap();
+
+    let schema = Schema::load(file.path()).unwrap();
+    assert!(schema.validate(r#""ok""#).is_ok());
+    assert!(schema.validate("1").is_err());
+}
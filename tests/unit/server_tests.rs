@@ -0,0 +1,309 @@
+use mergil::api::{ApiError, Role, Turn};
+use mergil::provider::{FallbackProvider, MockProvider, Provider};
+use mergil::redact::Redactor;
+use mergil::server::{self, ServerOptions};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+const TOKEN: &str = "test-token";
+
+/// Starts the server on a free port and returns its base URL.
+async fn start(provider: Arc<dyn Provider>) -> String {
+    let options = ServerOptions {
+        token: TOKEN.to_string(),
+        default_model: "default/model".to_string(),
+        aliases: HashMap::from([("gpt".to_string(), "openai/gpt-4o".to_string())]),
+        models: vec!["default/model".to_string(), "cheap/model".to_string()],
+        redactor: Some(Redactor::new(&[]).unwrap()),
+    };
+    let router = server::router(provider, options);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1", listener.local_addr().unwrap());
+    tokio::spawn(server::serve(listener, router));
+    url
+}
+
+fn post(url: &str, body: Value) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .post(format!("{}/chat/completions", url))
+        .bearer_auth(TOKEN)
+        .json(&body)
+}
+
+#[tokio::test]
+async fn test_chat_completion() {
+    let provider = Arc::new(MockProvider::new().with_response("Hello there"));
+    let url = start(provider.clone()).await;
+
+    let response = post(
+        &url,
+        json!({
+            "model": "gpt",
+            "temperature": 0.2,
+            "messages": [
+                { "role": "system", "content": "Be brief" },
+                { "role": "user", "content": "Hi" },
+                { "role": "assistant", "content": "Hello" },
+                { "role": "user", "content": [{ "type": "text", "text": "Again" }] },
+            ],
+        }),
+    )
+    .send()
+    .await
+    .unwrap();
+
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["model"], "openai/gpt-4o");
+    assert_eq!(body["choices"][0]["message"]["content"], "Hello there");
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+
+    let request = &provider.requests()[0];
+    assert_eq!(request.model, "openai/gpt-4o");
+    assert_eq!(request.temperature, Some(0.2));
+    assert_eq!(request.system, vec!["Be brief"]);
+    assert_eq!(
+        request.history,
+        vec![
+            Turn {
+                role: Role::User,
+                content: "Hi".to_string()
+            },
+            Turn {
+                role: Role::Assistant,
+                content: "Hello".to_string()
+            },
+        ]
+    );
+    assert_eq!(request.contents, vec!["Again"]);
+}
+
+#[tokio::test]
+async fn test_chat_completion_images() {
+    let image = json!({
+        "role": "user",
+        "content": [
+            { "type": "text", "text": "What is this?" },
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } },
+        ],
+    });
+
+    let provider = Arc::new(
+        MockProvider::new()
+            .with_image_support()
+            .with_response("A pixel"),
+    );
+    let url = start(provider.clone()).await;
+    let response = post(&url, json!({ "messages": [image] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let request = &provider.requests()[0];
+    assert_eq!(request.contents, vec!["What is this?"]);
+    assert_eq!(request.images[0].mime, "image/png");
+    assert_eq!(request.images[0].data, "iVBORw0KGgo=");
+
+    let provider = Arc::new(MockProvider::new().with_response("Unused"));
+    let url = start(provider.clone()).await;
+    let response = post(&url, json!({ "messages": [image] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        "The selected provider or model does not support image input"
+    );
+    assert!(provider.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_chat_completion_rejects_unknown_parts() {
+    let url = start(Arc::new(MockProvider::new())).await;
+    let response = post(
+        &url,
+        json!({
+            "messages": [{
+                "role": "user",
+                "content": [{ "type": "input_audio", "input_audio": { "data": "", "format": "wav" } }],
+            }],
+        }),
+    )
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_chat_completion_uses_default_model_and_redacts() {
+    let provider = Arc::new(MockProvider::new().with_response("Done"));
+    let url = start(provider.clone()).await;
+
+    let response = post(
+        &url,
+        json!({
+            "messages": [{ "role": "user", "content": "key sk-abcdefghijklmnopqrstuvwx" }],
+        }),
+    )
+    .send()
+    .await
+    .unwrap();
+
+    assert_eq!(response.status(), 200);
+    let request = &provider.requests()[0];
+    assert_eq!(request.model, "default/model");
+    assert_eq!(request.contents, vec!["key [REDACTED:api-key]"]);
+}
+
+#[tokio::test]
+async fn test_chat_completion_stream() {
+    let provider = Arc::new(MockProvider::new().with_response("One two three"));
+    let url = start(provider).await;
+
+    let response = post(
+        &url,
+        json!({
+            "model": "openai/gpt-4o",
+            "stream": true,
+            "messages": [{ "role": "user", "content": "Count" }],
+        }),
+    )
+    .send()
+    .await
+    .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/event-stream"));
+    let text = response.text().await.unwrap();
+    let events: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(events.last(), Some(&"[DONE]"));
+
+    let chunks: Vec<Value> = events[..events.len() - 1]
+        .iter()
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk["object"] == "chat.completion.chunk"));
+    assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+    let content: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(content, "One two three");
+    assert_eq!(
+        chunks.last().unwrap()["choices"][0]["finish_reason"],
+        "stop"
+    );
+}
+
+#[tokio::test]
+async fn test_chat_completion_stream_names_fallback_model() {
+    let provider = FallbackProvider::new(
+        MockProvider::new()
+            .with_error(ApiError::RateLimited("busy".to_string()))
+            .with_response("From two"),
+        vec!["test/one".to_string(), "test/two".to_string()],
+    );
+    let url = start(Arc::new(provider)).await;
+
+    let response = post(
+        &url,
+        json!({
+            "model": "test/one",
+            "stream": true,
+            "messages": [{ "role": "user", "content": "Hi" }],
+        }),
+    )
+    .send()
+    .await
+    .unwrap();
+
+    let text = response.text().await.unwrap();
+    let chunks: Vec<Value> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|event| *event != "[DONE]")
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+    assert!(!chunks.is_empty());
+    assert!(chunks.iter().all(|chunk| chunk["model"] == "test/two"));
+}
+
+#[tokio::test]
+async fn test_rejects_missing_or_wrong_token() {
+    let url = start(Arc::new(MockProvider::new())).await;
+    let client = reqwest::Client::new();
+
+    let missing = client.get(format!("{}/models", url)).send().await.unwrap();
+    assert_eq!(missing.status(), 401);
+
+    let wrong = client
+        .get(format!("{}/models", url))
+        .bearer_auth("nope")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(wrong.status(), 401);
+    let body: Value = wrong.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "invalid_api_key");
+}
+
+#[tokio::test]
+async fn test_models() {
+    let url = start(Arc::new(MockProvider::new())).await;
+
+    let body: Value = reqwest::Client::new()
+        .get(format!("{}/models", url))
+        .bearer_auth(TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let ids: Vec<&str> = body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["default/model", "cheap/model"]);
+}
+
+#[tokio::test]
+async fn test_chat_completion_errors() {
+    let provider =
+        Arc::new(MockProvider::new().with_error(ApiError::RateLimited("slow down".to_string())));
+    let url = start(provider).await;
+
+    let invalid = post(&url, json!({ "messages": "nope" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), 400);
+
+    let limited = post(
+        &url,
+        json!({ "messages": [{ "role": "user", "content": "Hi" }] }),
+    )
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(limited.status(), 429);
+    let body: Value = limited.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "rate_limited");
+}
@@ -0,0 +1,216 @@
+use mergil::provider::MockProvider;
+use mergil::shell::{self, CommandExecutor, Suggestion};
+use std::cell::RefCell;
+use std::io;
+
+struct MockExecutor {
+    executed: RefCell<Vec<String>>,
+}
+
+impl MockExecutor {
+    fn new() -> Self {
+        MockExecutor {
+            executed: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl CommandExecutor for MockExecutor {
+    fn execute(&self, command: &str) -> io::Result<Option<i32>> {
+        self.executed.borrow_mut().push(command.to_string());
+        Ok(Some(0))
+    }
+}
+
+#[test]
+fn test_parse_suggestion() {
+    let suggestion = shell::parse_suggestion(
+        "COMMAND: `find . -size +100M`\nEXPLANATION: Lists files larger than 100 MB.",
+    );
+    assert_eq!(
+        suggestion,
+        Suggestion {
+            command: "find . -size +100M".to_string(),
+            explanation: "Lists files larger than 100 MB.".to_string(),
+        }
+    );
+
+    let suggestion = shell::parse_suggestion("```bash\ndu -sh *\n```");
+    assert_eq!(suggestion.command, "du -sh *");
+    assert_eq!(suggestion.explanation, "");
+}
+
+#[test]
+fn test_destructive_reason() {
+    assert!(shell::destructive_reason("rm -rf build/").is_some());
+    assert!(shell::destructive_reason("sudo rm -r /tmp/x").is_some());
+    assert!(shell::destructive_reason("dd if=/dev/zero of=/dev/sda bs=1M").is_some());
+    assert!(shell::destructive_reason("sudo mkfs.ext4 /dev/sdb1").is_some());
+    assert!(shell::destructive_reason("git push --force origin main").is_some());
+    assert!(shell::destructive_reason("git push -f").is_some());
+    assert!(shell::destructive_reason("cd repo && git reset --hard HEAD~1").is_some());
+
+    assert!(shell::destructive_reason("rm notes.txt").is_none());
+    assert!(shell::destructive_reason("git push origin main").is_none());
+    assert!(shell::destructive_reason("find . -name '*.rs' | xargs wc -l").is_none());
+    assert!(shell::destructive_reason("echo add").is_none());
+}
+
+#[test]
+fn test_destructive_reason_looks_through_wrappers() {
+    assert!(shell::destructive_reason("find . | xargs rm -rf").is_some());
+    assert!(shell::destructive_reason("find . -print0 | xargs -0 -n 10 rm -r").is_some());
+    assert!(shell::destructive_reason("find . -exec rm -rf {} +").is_some());
+    assert!(shell::destructive_reason("find . -name '*.o' -execdir rm -r {} \\;").is_some());
+    assert!(shell::destructive_reason("sudo -u root rm -r /").is_some());
+    assert!(shell::destructive_reason("doas -u admin /bin/rm -fr /srv").is_some());
+    assert!(shell::destructive_reason("env -i PATH=/bin rm -rf /tmp/x").is_some());
+    assert!(shell::destructive_reason("sudo -E chown -R me /srv").is_some());
+
+    assert!(shell::destructive_reason("find . -exec cat {} +").is_none());
+    assert!(shell::destructive_reason("sudo -u root ls /").is_none());
+    assert!(shell::destructive_reason("env FOO=1 cargo test").is_none());
+}
+
+#[test]
+fn test_destructive_reason_normalizes_flags() {
+    assert!(shell::destructive_reason("rm -rfv dir").is_some());
+    assert!(shell::destructive_reason("rm -fR /x").is_some());
+    assert!(shell::destructive_reason("rm -v --recursive dir").is_some());
+    assert!(shell::destructive_reason("git clean -xdf").is_some());
+    assert!(shell::destructive_reason("git -C repo push -uf origin main").is_some());
+    assert!(shell::destructive_reason("git push --force-with-lease=main origin").is_some());
+
+    assert!(shell::destructive_reason("rm -fv notes.txt").is_none());
+    assert!(shell::destructive_reason("rm -- -r").is_none());
+    assert!(shell::destructive_reason("git clean -n").is_none());
+}
+
+#[test]
+fn test_destructive_reason_force_refspec() {
+    assert!(shell::destructive_reason("git push origin +main").is_some());
+    assert!(shell::destructive_reason("git push origin +HEAD:refs/heads/main").is_some());
+    assert!(shell::destructive_reason("git push origin main:main").is_none());
+}
+
+#[test]
+fn test_destructive_reason_checks_shell_scripts() {
+    assert!(shell::destructive_reason("sh -c 'rm -rf /tmp/x'").is_some());
+    assert!(shell::destructive_reason("sudo bash -c \"cd /srv && rm -r data\"").is_some());
+    assert!(shell::destructive_reason("zsh -ec 'git push -f'").is_some());
+    assert!(shell::destructive_reason("ls | xargs dash -c 'shred \"$0\"'").is_some());
+
+    assert!(shell::destructive_reason("sh -c 'ls -la'").is_none());
+    assert!(shell::destructive_reason("bash script.sh").is_none());
+}
+
+#[test]
+fn test_destructive_reason_find_delete() {
+    assert!(shell::destructive_reason("find . -name '*.o' -delete").is_some());
+    assert!(shell::destructive_reason("sudo find /tmp -mtime +7 -delete").is_some());
+
+    assert!(shell::destructive_reason("find . -name '*.o' -print").is_none());
+}
+
+#[test]
+fn test_system_prompt_mentions_environment() {
+    let prompt = shell::system_prompt();
+    assert!(prompt.contains(std::env::consts::OS));
+    assert!(prompt.contains("COMMAND:"));
+}
+
+#[tokio::test]
+async fn test_run_executes_command() {
+    let provider = MockProvider::new()
+        .with_response("COMMAND: du -ah . | sort -rh | head\nEXPLANATION: Largest files first.");
+    let executor = MockExecutor::new();
+    let mut input = io::Cursor::new("e\n");
+    let mut out = Vec::new();
+
+    shell::run(
+        "find large files",
+        "test-model",
+        &provider,
+        &mut input,
+        &mut out,
+        &executor,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        *executor.executed.borrow(),
+        vec!["du -ah . | sort -rh | head".to_string()]
+    );
+    let output = String::from_utf8(out).unwrap();
+    assert!(output.contains("Largest files first."));
+    assert!(!output.contains("WARNING"));
+
+    let request = &provider.requests()[0];
+    assert_eq!(request.contents, vec!["find large files".to_string()]);
+    assert_eq!(request.system, vec![shell::system_prompt()]);
+}
+
+#[tokio::test]
+async fn test_run_requires_confirmation_for_destructive_commands() {
+    let executor = MockExecutor::new();
+
+    let provider = MockProvider::new().with_response("COMMAND: rm -rf target");
+    let mut input = io::Cursor::new("e\nno\n");
+    let mut out = Vec::new();
+    shell::run("clean", "m", &provider, &mut input, &mut out, &executor)
+        .await
+        .unwrap();
+    assert!(executor.executed.borrow().is_empty());
+    assert!(String::from_utf8(out).unwrap().contains("WARNING"));
+
+    let provider = MockProvider::new().with_response("COMMAND: rm -rf target");
+    let mut input = io::Cursor::new("e\nyes\n");
+    shell::run(
+        "clean",
+        "m",
+        &provider,
+        &mut input,
+        &mut Vec::new(),
+        &executor,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        *executor.executed.borrow(),
+        vec!["rm -rf target".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn test_run_revise_then_quit() {
+    let provider = MockProvider::new()
+        .with_response("COMMAND: ls -la")
+        .with_response("COMMAND: ls -lah");
+    let executor = MockExecutor::new();
+    let mut input = io::Cursor::new("r\nhuman readable sizes\nq\n");
+    let mut out = Vec::new();
+
+    shell::run(
+        "list files",
+        "m",
+        &provider,
+        &mut input,
+        &mut out,
+        &executor,
+    )
+    .await
+    .unwrap();
+
+    let requests = provider.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[1].contents,
+        vec![
+            "list files".to_string(),
+            "You suggested: ls -la\nRevise it: human readable sizes".to_string()
+        ]
+    );
+    assert!(executor.executed.borrow().is_empty());
+    assert!(String::from_utf8(out).unwrap().contains("ls -lah"));
+}
@@ -0,0 +1,101 @@
+use mergil::template::{self, Template, TemplateError};
+use std::collections::HashMap;
+use std::fs;
+
+#[test]
+fn test_parse_front_matter() {
+    let text = "---\ndescription: Explain an error\nmodel: \"deepseek/deepseek-coder\"\nmarkdown: true\ntemperature: 0.2\n---\nExplain {{input}}\n";
+
+    let template = Template::parse("explain-error", text).unwrap();
+    assert_eq!(template.name, "explain-error");
+    assert_eq!(template.description.as_deref(), Some("Explain an error"));
+    assert_eq!(template.model.as_deref(), Some("deepseek/deepseek-coder"));
+    assert_eq!(template.markdown, Some(true));
+    assert_eq!(template.temperature, Some(0.2));
+    assert_eq!(template.body, "Explain {{input}}\n");
+}
+
+#[test]
+fn test_parse_without_front_matter() {
+    let template = Template::parse("plain", "Just {{input}}").unwrap();
+    assert_eq!(template.model, None);
+    assert_eq!(template.body, "Just {{input}}");
+}
+
+#[test]
+fn test_parse_invalid_front_matter() {
+    let unknown = Template::parse("bad", "---\ncolour: red\n---\nbody");
+    assert!(matches!(
+        unknown,
+        Err(TemplateError::InvalidFrontMatter(_, _))
+    ));
+
+    let unclosed = Template::parse("bad", "---\nmodel: x\nbody");
+    assert!(matches!(
+        unclosed,
+        Err(TemplateError::InvalidFrontMatter(_, _))
+    ));
+
+    let not_a_number = Template::parse("bad", "---\ntemperature: warm\n---\nbody");
+    assert!(matches!(
+        not_a_number,
+        Err(TemplateError::InvalidFrontMatter(_, _))
+    ));
+}
+
+#[test]
+fn test_render_placeholders() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("notes.txt");
+    fs::write(&file, "file contents").unwrap();
+
+    let body = format!(
+        "Lang: {{{{ lang }}}}\nInput: {{{{input}}}}\nFile: {{{{file:{}}}}}\nShell: {{{{shell:echo hi}}}}",
+        file.display()
+    );
+    let template = Template::parse("test", &body).unwrap();
+    let vars = HashMap::from([("lang".to_string(), "rust".to_string())]);
+
+    let rendered = template.render("the input", &vars).unwrap();
+    assert_eq!(
+        rendered,
+        "Lang: rust\nInput: the input\nFile: file contents\nShell: hi\n"
+    );
+    assert!(template.uses_input());
+}
+
+#[test]
+fn test_render_errors() {
+    let template = Template::parse("test", "{{lang}}").unwrap();
+    let result = template.render("", &HashMap::new());
+    assert!(matches!(result, Err(TemplateError::MissingVariable(name)) if name == "lang"));
+    assert!(!template.uses_input());
+
+    let template = Template::parse("test", "{{shell:exit 3}}").unwrap();
+    let result = template.render("", &HashMap::new());
+    assert!(matches!(
+        result,
+        Err(TemplateError::PlaceholderFailed(_, _))
+    ));
+}
+
+#[test]
+fn test_list_and_load() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("b.md"), "---\ndescription: Second\n---\nB").unwrap();
+    fs::write(dir.path().join("a.md"), "A").unwrap();
+    fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+    let templates = template::list(dir.path()).unwrap();
+    let names: Vec<_> = templates.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["a", "b"]);
+
+    assert_eq!(Template::load(dir.path(), "b").unwrap().body, "B");
+    assert!(matches!(
+        Template::load(dir.path(), "missing"),
+        Err(TemplateError::NotFound(_, _))
+    ));
+    assert!(template::list(&dir.path().join("missing"))
+        .unwrap()
+        .is_empty());
+}
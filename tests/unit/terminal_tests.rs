@@ -0,0 +1,157 @@
+use mergil::terminal::{color_enabled, pager_command, render_markdown, ColorChoice, Display};
+use std::collections::HashMap;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+#[test]
+fn test_color_enabled_auto_follows_terminal() {
+    assert!(color_enabled(ColorChoice::Auto, true, env(&[])));
+    assert!(!color_enabled(ColorChoice::Auto, false, env(&[])));
+}
+
+#[test]
+fn test_color_enabled_env() {
+    assert!(!color_enabled(
+        ColorChoice::Auto,
+        true,
+        env(&[("NO_COLOR", "1")])
+    ));
+    assert!(color_enabled(
+        ColorChoice::Auto,
+        true,
+        env(&[("NO_COLOR", "")])
+    ));
+    assert!(color_enabled(
+        ColorChoice::Auto,
+        false,
+        env(&[("CLICOLOR_FORCE", "1")])
+    ));
+    assert!(!color_enabled(
+        ColorChoice::Auto,
+        false,
+        env(&[("CLICOLOR_FORCE", "0")])
+    ));
+    assert!(!color_enabled(
+        ColorChoice::Auto,
+        false,
+        env(&[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")])
+    ));
+}
+
+#[test]
+fn test_color_enabled_flag_wins() {
+    let vars = [("NO_COLOR", "1")];
+    assert!(color_enabled(ColorChoice::Always, false, env(&vars)));
+    assert!(!color_enabled(
+        ColorChoice::Never,
+        true,
+        env(&[("CLICOLOR_FORCE", "1")])
+    ));
+}
+
+#[test]
+fn test_pager_command() {
+    assert_eq!(pager_command(None), Some(vec!["less".to_string()]));
+    assert_eq!(
+        pager_command(Some("less -R --quit-if-one-screen")),
+        Some(vec![
+            "less".to_string(),
+            "-R".to_string(),
+            "--quit-if-one-screen".to_string()
+        ])
+    );
+    assert_eq!(pager_command(Some("")), None);
+    assert_eq!(pager_command(Some("cat")), None);
+}
+
+#[test]
+fn test_render_markdown_plain() {
+    let text = render_markdown("# Title\n\nSome **bold** text", false, None);
+    assert!(text.contains("Title"));
+    assert!(text.contains("Some bold text"));
+    assert!(!text.contains("**"));
+    assert!(!text.contains('\x1b'));
+}
+
+#[test]
+fn test_render_markdown_color() {
+    let text = render_markdown("Some **bold** text", true, None);
+    assert!(text.contains('\x1b'));
+}
+
+#[test]
+fn test_render_markdown_width() {
+    let long = "word ".repeat(40);
+    let wrapped = render_markdown(&long, false, Some(20));
+    assert!(wrapped.lines().count() > 1);
+    assert!(wrapped.lines().all(|line| line.chars().count() <= 20));
+
+    let unwrapped = render_markdown(&long, false, None);
+    assert_eq!(unwrapped.lines().count(), 1);
+}
+
+#[test]
+fn test_display_without_terminal_does_not_page() {
+    let display = Display::detect(ColorChoice::Never, None, false, false);
+    assert_eq!(display.width, None);
+    assert_eq!(display.page_from, None);
+    assert!(!display.color);
+
+    let mut out = Vec::new();
+    let answer = "line\n".repeat(500);
+    display.print(&answer, false, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), format!("{}\n", answer));
+}
+
+#[test]
+fn test_display_without_terminal_writes_markdown_source() {
+    let table = "| Name | Size |\n|------|------|\n| a.rs | 10 |\n\n---\n\nSome **bold** text";
+    let display = Display::detect(ColorChoice::Auto, None, false, false);
+    assert!(!display.render);
+
+    let mut out = Vec::new();
+    display.print(table, true, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), format!("{}\n", table));
+
+    let display = Display::detect(ColorChoice::Always, None, false, false);
+    assert!(display.render);
+}
+
+#[test]
+fn test_display_pages_tall_output() {
+    // `true` stands in for the pager, so nothing reaches `out`.
+    let display = Display {
+        page_from: Some(10),
+        pager: Some(vec!["true".to_string()]),
+        ..Display::default()
+    };
+
+    let mut out = Vec::new();
+    display.print("short", false, &mut out).unwrap();
+    assert_eq!(out, b"short\n");
+
+    let mut out = Vec::new();
+    display
+        .print(&"line\n".repeat(20), false, &mut out)
+        .unwrap();
+    assert!(out.is_empty());
+}
+
+#[test]
+fn test_display_falls_back_when_pager_is_missing() {
+    let display = Display {
+        page_from: Some(1),
+        pager: Some(vec!["mergil-no-such-pager".to_string()]),
+        ..Display::default()
+    };
+
+    let mut out = Vec::new();
+    display.print("answer", false, &mut out).unwrap();
+    assert_eq!(out, b"answer\n");
+}